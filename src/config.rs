@@ -0,0 +1,515 @@
+// editor options, changed at runtime with `:set`
+pub struct Config {
+    pub visualbell: bool, // flash the status bar when an action fails
+    pub cursorline: bool, // highlight the row the cursor is on
+    pub colorcolumn: usize, // column of the guide (1 based), 0 is off
+    pub textwidth: usize,   // wrap typed text after this column, 0 is off
+    pub wrapmargin: usize,  // without textwidth, wrap this many columns before the window edge
+    pub spell: bool,        // highlight misspelled words in comments and strings
+    pub autopair: bool,     // insert the closing bracket/quote with the opening one
+    pub cmdheight: usize,   // rows of the message bar
+    pub laststatus: usize,  // 2 shows the status bar, 0 (or 1) hides it
+    pub joinspaces: bool,   // `J` puts two spaces after a '.', '!' or '?'
+    pub sidescrolloff: usize, // columns kept visible left and right of the cursor
+    pub tabstop: usize,    // spaces typed for a Tab
+    pub shiftwidth: usize, // spaces of one indent step, 0 uses tabstop
+    pub ignorecase: bool,  // searches and substitutions ignore case
+    pub smartcase: bool,   // ...unless the pattern has an uppercase letter
+    pub guicursor: GuiCursor, // the cursor in each mode
+    pub paste: bool,       // typed text is inserted as is, for pasting in the terminal
+    pub makeprg: String,   // the command run by `:make`
+    pub wildmenu: bool,    // list the candidates of Tab completion on the command line
+    pub emacskeys: bool,   // `Ctrl-A` / `Ctrl-E` go to the start / end of the row in insert mode
+    pub rainbow: bool,     // color brackets by nesting depth
+    pub matchpairs: Vec<(char, char)>, // the pairs `%` jumps between
+    pub trimtrailing: bool, // remove trailing blanks when saving
+    pub incsearch: bool,   // `/` moves to the match while the query is typed
+    pub virtualedit: bool, // the cursor can move past the end of rows (`virtualedit=all`)
+    pub timeout: bool,     // give up a multi-key command when the next key doesn't come...
+    pub timeoutlen: usize, // ...within this many milliseconds
+    pub gitbranch: bool,   // show the git branch of the working directory in the status bar
+    pub list: bool,        // show tabs, trailing spaces and the ends of rows...
+    pub listchars: ListChars, // ...as these characters
+    pub backspace: Backspace, // what Backspace can delete in insert mode
+    pub nrformats: NrFormats, // what `Ctrl-A` / `Ctrl-X` change besides decimal numbers
+    pub whichwrap: WhichWrap, // the normal mode keys that move to the previous / next row
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+impl CursorStyle {
+    // `block`, `underline` (or `hor`) or `bar` (or `ver`), then `-blink` to
+    // make it blink
+    fn parse(text: &str) -> Option<Self> {
+        let (shape, blink) = match text.strip_suffix("-blink") {
+            Some(shape) => (shape, true),
+            None => (text, false),
+        };
+        let shape = match shape {
+            "block" => CursorShape::Block,
+            "underline" | "hor" => CursorShape::Underline,
+            "bar" | "ver" => CursorShape::Bar,
+            _ => return None,
+        };
+        Some(Self { shape, blink })
+    }
+}
+
+// `:set guicursor=n-v:block,i:bar-blink`, comma separated modes (`n`ormal,
+// `i`nsert, `v`isual or `r`eplace, joined by '-') and the style of the
+// cursor in them
+#[derive(Clone, Copy)]
+pub struct GuiCursor {
+    pub normal: CursorStyle,
+    pub insert: CursorStyle,
+    pub visual: CursorStyle,
+    pub replace: CursorStyle,
+}
+
+impl Default for GuiCursor {
+    fn default() -> Self {
+        Self {
+            normal: CursorStyle { shape: CursorShape::Block, blink: true },
+            insert: CursorStyle { shape: CursorShape::Bar, blink: true },
+            visual: CursorStyle { shape: CursorShape::Block, blink: false },
+            replace: CursorStyle { shape: CursorShape::Underline, blink: true },
+        }
+    }
+}
+
+impl GuiCursor {
+    fn set(&mut self, value: &str) -> Option<()> {
+        let mut guicursor = *self;
+        for part in value.split(',') {
+            let (modes, style) = part.split_once(':')?;
+            let style = CursorStyle::parse(style)?;
+            for mode in modes.split('-') {
+                match mode {
+                    "n" => guicursor.normal = style,
+                    "i" => guicursor.insert = style,
+                    "v" => guicursor.visual = style,
+                    "r" => guicursor.replace = style,
+                    _ => return None,
+                }
+            }
+        }
+        *self = guicursor;
+        Some(())
+    }
+}
+
+// `:set listchars=tab:>-,trail:.,eol:$`, comma separated kinds of
+// characters and what they are drawn as with `list`, the ones not given are
+// drawn as they are
+#[derive(Clone, Copy)]
+pub struct ListChars {
+    // a tab takes a single column, it's drawn as the first of two
+    // characters given (`tab:>-`), or the last of three (`tab:<->`)
+    pub tab: Option<char>,
+    pub trail: Option<char>, // spaces at the end of the row
+    pub space: Option<char>, // the other spaces
+    pub eol: Option<char>,   // after the last character of the row
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: None,
+            trail: None,
+            space: None,
+            eol: Some('$'),
+        }
+    }
+}
+
+impl ListChars {
+    fn parse(value: &str) -> Option<Self> {
+        let mut listchars = Self {
+            tab: None,
+            trail: None,
+            space: None,
+            eol: None,
+        };
+        for part in value.split(',') {
+            let (kind, chars) = part.split_once(':')?;
+            let chars: Vec<char> = chars.chars().collect();
+            match (kind, chars.len()) {
+                ("tab", 2) => listchars.tab = chars.first().copied(),
+                ("tab", 3) => listchars.tab = chars.last().copied(),
+                ("trail", 1) => listchars.trail = chars.first().copied(),
+                ("space", 1) => listchars.space = chars.first().copied(),
+                ("eol", 1) => listchars.eol = chars.first().copied(),
+                _ => return None,
+            }
+        }
+        Some(listchars)
+    }
+}
+
+// `:set backspace=indent,eol,start`, comma separated things Backspace (and
+// `Ctrl-W` / `Ctrl-U`) may delete in insert mode: the indentation of the
+// row, the line break before it and the text before where insert mode was
+// entered
+#[derive(Clone, Copy)]
+pub struct Backspace {
+    pub indent: bool,
+    pub eol: bool,
+    pub start: bool,
+}
+
+impl Default for Backspace {
+    fn default() -> Self {
+        Self {
+            indent: true,
+            eol: true,
+            start: true,
+        }
+    }
+}
+
+impl Backspace {
+    fn parse(value: &str) -> Option<Self> {
+        let mut backspace = Self {
+            indent: false,
+            eol: false,
+            start: false,
+        };
+        for part in value.split(',').filter(|part| !part.is_empty()) {
+            match part {
+                "indent" => backspace.indent = true,
+                "eol" => backspace.eol = true,
+                "start" => backspace.start = true,
+                _ => return None,
+            }
+        }
+        Some(backspace)
+    }
+}
+
+// `:set nrformats=hex,alpha,date`, comma separated things `Ctrl-A` /
+// `Ctrl-X` change besides decimal numbers: hex numbers (`0x1f`), single
+// letters and dates (`2024-01-31`)
+#[derive(Clone, Copy, Default)]
+pub struct NrFormats {
+    pub hex: bool,
+    pub alpha: bool,
+    pub date: bool,
+}
+
+impl NrFormats {
+    fn parse(value: &str) -> Option<Self> {
+        let mut formats = Self::default();
+        for part in value.split(',').filter(|part| !part.is_empty()) {
+            match part {
+                "hex" => formats.hex = true,
+                "alpha" => formats.alpha = true,
+                "date" => formats.date = true,
+                _ => return None,
+            }
+        }
+        Some(formats)
+    }
+}
+
+// `:set whichwrap=b,s,h,l,<,>`, comma separated normal mode keys that go
+// on to the previous / next row at the start / end of a row: Backspace,
+// Space, `h`, `l` and the Left / Right arrows
+#[derive(Clone, Copy)]
+pub struct WhichWrap {
+    pub backspace: bool,
+    pub space: bool,
+    pub h: bool,
+    pub l: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Default for WhichWrap {
+    fn default() -> Self {
+        Self {
+            backspace: true,
+            space: true,
+            h: false,
+            l: false,
+            left: false,
+            right: false,
+        }
+    }
+}
+
+impl WhichWrap {
+    fn parse(value: &str) -> Option<Self> {
+        let mut whichwrap = Self {
+            backspace: false,
+            space: false,
+            ..Self::default()
+        };
+        for part in value.split(',').filter(|part| !part.is_empty()) {
+            match part {
+                "b" => whichwrap.backspace = true,
+                "s" => whichwrap.space = true,
+                "h" => whichwrap.h = true,
+                "l" => whichwrap.l = true,
+                "<" => whichwrap.left = true,
+                ">" => whichwrap.right = true,
+                _ => return None,
+            }
+        }
+        Some(whichwrap)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            visualbell: false,
+            cursorline: false,
+            colorcolumn: 0,
+            textwidth: 0,
+            wrapmargin: 0,
+            spell: false,
+            autopair: false,
+            cmdheight: 1,
+            laststatus: 2,
+            joinspaces: false,
+            sidescrolloff: 5,
+            tabstop: 4,
+            shiftwidth: 0,
+            ignorecase: false,
+            smartcase: false,
+            guicursor: GuiCursor::default(),
+            paste: false,
+            makeprg: "cargo build".to_owned(),
+            wildmenu: true,
+            emacskeys: false,
+            rainbow: false,
+            matchpairs: vec![('(', ')'), ('{', '}'), ('[', ']')],
+            trimtrailing: false,
+            incsearch: true,
+            virtualedit: false,
+            timeout: true,
+            timeoutlen: 1000,
+            gitbranch: false,
+            list: false,
+            listchars: ListChars::default(),
+            backspace: Backspace::default(),
+            nrformats: NrFormats::default(),
+            whichwrap: WhichWrap::default(),
+        }
+    }
+}
+
+impl Config {
+    // `:set` takes whitespace separated options of the form
+    //   name      turn a boolean option on
+    //   noname    turn a boolean option off
+    //   name=N    set a number option
+    //   guicursor=modes:style,...
+    //   makeprg=command   a backslash escapes a space in it
+    //   matchpairs=(:),{:}
+    //   virtualedit=all   or empty to turn it off
+    //   listchars=tab:>-,trail:.,eol:$
+    //   backspace=indent,eol,start
+    //   nrformats=hex,alpha,date
+    //   whichwrap=b,s,h,l,<,>
+    pub fn set(&mut self, args: &str) -> Result<(), String> {
+        for arg in split_args(args) {
+            self.set_one(&arg)?;
+        }
+        Ok(())
+    }
+
+    fn set_one(&mut self, arg: &str) -> Result<(), String> {
+        if let Some((name, value)) = arg.split_once('=') {
+            if let "guicursor" | "gcr" = name {
+                return self
+                    .guicursor
+                    .set(value)
+                    .ok_or_else(|| format!("Invalid value for {}: {}", name, value));
+            }
+            if let "makeprg" | "mp" = name {
+                self.makeprg = value.to_owned();
+                return Ok(());
+            }
+            if let "matchpairs" | "mps" = name {
+                self.matchpairs =
+                    parse_pairs(value).ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+                return Ok(());
+            }
+            if let "listchars" | "lcs" = name {
+                self.listchars =
+                    ListChars::parse(value).ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+                return Ok(());
+            }
+            if let "backspace" | "bs" = name {
+                self.backspace =
+                    Backspace::parse(value).ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+                return Ok(());
+            }
+            if let "nrformats" | "nf" = name {
+                self.nrformats =
+                    NrFormats::parse(value).ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+                return Ok(());
+            }
+            if let "whichwrap" | "ww" = name {
+                self.whichwrap =
+                    WhichWrap::parse(value).ok_or_else(|| format!("Invalid value for {}: {}", name, value))?;
+                return Ok(());
+            }
+            if let "virtualedit" | "ve" = name {
+                self.virtualedit = match value {
+                    "all" => true,
+                    "" => false,
+                    _ => return Err(format!("Invalid value for {}: {}", name, value)),
+                };
+                return Ok(());
+            }
+            let option = self
+                .number_option(name)
+                .ok_or_else(|| format!("Unknown option: {}", name))?;
+            let value = value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}: {}", name, value))?;
+            if value == 0 && matches!(name, "tabstop" | "ts") {
+                return Err(format!("Invalid value for {}: {}", name, value));
+            }
+            *option = value;
+            return Ok(());
+        }
+        if let Some(option) = self.bool_option(arg) {
+            *option = true;
+            return Ok(());
+        }
+        if let Some(option) = arg.strip_prefix("no").and_then(|name| self.bool_option(name)) {
+            *option = false;
+            return Ok(());
+        }
+        Err(format!("Unknown option: {}", arg))
+    }
+
+    // the spaces of one indent step
+    pub fn shift_width(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+
+    // whether to ignore case when looking for `pattern`
+    pub fn ignore_case(&self, pattern: &str) -> bool {
+        self.ignorecase && !(self.smartcase && pattern.chars().any(char::is_uppercase))
+    }
+
+    fn bool_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "visualbell" | "vb" => Some(&mut self.visualbell),
+            "cursorline" | "cul" => Some(&mut self.cursorline),
+            "spell" => Some(&mut self.spell),
+            "autopair" | "ap" => Some(&mut self.autopair),
+            "joinspaces" | "js" => Some(&mut self.joinspaces),
+            "ignorecase" | "ic" => Some(&mut self.ignorecase),
+            "smartcase" | "scs" => Some(&mut self.smartcase),
+            "paste" => Some(&mut self.paste),
+            "wildmenu" | "wmnu" => Some(&mut self.wildmenu),
+            "emacskeys" => Some(&mut self.emacskeys),
+            "rainbow" => Some(&mut self.rainbow),
+            "trimtrailing" => Some(&mut self.trimtrailing),
+            "incsearch" | "is" => Some(&mut self.incsearch),
+            "timeout" | "to" => Some(&mut self.timeout),
+            "list" => Some(&mut self.list),
+            "gitbranch" => Some(&mut self.gitbranch),
+            _ => None,
+        }
+    }
+
+    fn number_option(&mut self, name: &str) -> Option<&mut usize> {
+        match name {
+            "colorcolumn" | "cc" => Some(&mut self.colorcolumn),
+            "textwidth" | "tw" => Some(&mut self.textwidth),
+            "wrapmargin" | "wm" => Some(&mut self.wrapmargin),
+            "cmdheight" | "ch" => Some(&mut self.cmdheight),
+            "laststatus" | "ls" => Some(&mut self.laststatus),
+            "sidescrolloff" | "siso" => Some(&mut self.sidescrolloff),
+            "tabstop" | "ts" => Some(&mut self.tabstop),
+            "shiftwidth" | "sw" => Some(&mut self.shiftwidth),
+            "timeoutlen" | "tm" => Some(&mut self.timeoutlen),
+            _ => None,
+        }
+    }
+}
+
+// `(:),{:}`, comma separated pairs of different characters
+fn parse_pairs(value: &str) -> Option<Vec<(char, char)>> {
+    value
+        .split(',')
+        .map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(':'), Some(close), None) if open != close => Some((open, close)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// `:set` arguments are separated by whitespace, a backslash makes the next
+// character part of the argument
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut arg = String::new();
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    result.push(arg);
+                    arg = String::new();
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        result.push(arg);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smartcase_ignores_case_only_for_lowercase_patterns() {
+        let mut config = Config::default();
+        assert!(!config.ignore_case("foo"));
+        assert!(config.set("ignorecase smartcase").is_ok());
+        assert!(config.ignore_case("foo"));
+        assert!(config.ignore_case("foo.*bar"));
+        assert!(!config.ignore_case("Foo"));
+        assert!(!config.ignore_case("fooBar"));
+    }
+
+    #[test]
+    fn ignorecase_alone_ignores_case_for_any_pattern() {
+        let mut config = Config::default();
+        assert!(config.set("ignorecase").is_ok());
+        assert!(config.ignore_case("foo"));
+        assert!(config.ignore_case("Foo"));
+        assert!(config.set("noignorecase smartcase").is_ok());
+        assert!(!config.ignore_case("foo"));
+    }
+}
@@ -1,45 +1,184 @@
+use crate::gap_buffer::GapBuffer;
+use crate::history::{self, History};
+use crate::spell::Dictionary;
 use crate::FileType;
 use crate::Pos;
 use crate::Row;
 use crate::SearchDirection;
+use regex::{Captures, Regex};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
 use std::io::{Error, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// past rows × lines changed rows, `changes` compares rows in order rather
+// than aligning them with the file. Aligning is redone after every edit, this
+// keeps it quick
+const MAX_DIFF_CELLS: usize = 250_000;
+
+// a closed fold, rows start..=end are shown as a single summary line
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+}
+
+// `:set foldmethod=`, what makes a fold: a row and the more indented rows
+// after it, or the rows from a `{{{` marker to its `}}}`
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum FoldMethod {
+    #[default]
+    Indent,
+    Marker,
+}
+
+impl FoldMethod {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "indent" => Some(Self::Indent),
+            "marker" => Some(Self::Marker),
+            _ => None,
+        }
+    }
+}
+
+// how a row differs from the file on disk
+#[derive(Clone, Copy, PartialEq)]
+pub enum Change {
+    Added,
+    Modified,
+}
 
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
-    dirty: bool,
+    rows: GapBuffer<Row>,
+    history: History,
     pub filename: Option<String>,
     filetype: FileType,
+    lossy: bool, // invalid UTF-8 bytes were replaced when opening
+    bom: bool,   // the file starts with a UTF-8 byte order mark, not part of the first row
+    folds: Vec<Fold>,
+    foldmethod: FoldMethod,
+    original: Option<Vec<String>>, // the rows as on disk, None for a new file
+    mtime: Option<SystemTime>, // modification time of the file when read or saved
+    locked: bool,              // `:set nomodifiable`, changes are refused
+    scratch: bool,             // shows command output, can't be saved
+    refused_change: bool,      // a change was refused since the last check
+    version: usize,            // counts the modifications of the rows
+    // `changes` as of a version, comparing a big document with the file
+    // on disk takes a while
+    changes: Option<(usize, Vec<Option<Change>>)>,
+    // the rows before this one are highlighted, each in the state the row
+    // before it ended in, so highlighting can go on from there
+    highlighted: usize,
 }
 
 impl Document {
-    pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let mut rows = Vec::new();
+    // with `lossy`, files that are not valid UTF-8 are opened anyway and the
+    // invalid bytes are replaced by U+FFFD
+    pub fn open(filename: &str, lossy: bool) -> Result<Self, std::io::Error> {
+        let mut is_lossy = false;
+        let contents = if lossy {
+            let bytes = fs::read(filename)?;
+            match String::from_utf8(bytes) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    is_lossy = true;
+                    String::from_utf8_lossy(err.as_bytes()).into_owned()
+                }
+            }
+        } else {
+            fs::read_to_string(filename)?
+        };
+        let (bom, contents) = match contents.strip_prefix('\u{feff}') {
+            Some(rest) => (true, rest.to_owned()),
+            None => (false, contents),
+        };
+        let rows = contents.lines().map(Row::from).collect();
         let filetype = FileType::from(filename);
-
-        for value in contents.lines() {
-            rows.push(Row::from(value));
-        }
+        let original = Some(contents.lines().map(str::to_owned).collect());
+        // the undo history of the last session, if the file was not changed since
+        let history = undo_file(filename)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| History::deserialize(&text, history::hash(&contents)))
+            .unwrap_or_default();
         Ok(Self {
             rows,
-            dirty: false,
+            history,
             filename: Some(filename.to_string()),
             filetype: filetype,
+            lossy: is_lossy,
+            bom,
+            folds: Vec::new(),
+            foldmethod: FoldMethod::default(),
+            original,
+            mtime: modified(filename),
+            locked: false,
+            scratch: false,
+            refused_change: false,
+            version: 0,
+            changes: None,
+            highlighted: 0,
         })
     }
 
+    // an empty document written to `filename` when saved, for `:e` on a
+    // file that does not exist yet
+    pub fn new_file(filename: &str) -> Self {
+        Self {
+            filename: Some(filename.to_owned()),
+            filetype: FileType::from(filename),
+            ..Self::default()
+        }
+    }
+
+    // a document showing `lines` (e.g. the output of `:registers`), it is
+    // not modifiable and has no file
+    pub fn scratch(lines: &[String]) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(line.as_str())).collect(),
+            locked: true,
+            scratch: true,
+            ..Self::default()
+        }
+    }
+
+    // a modifiable document of `lines` without a file
+    #[cfg(test)]
+    pub fn from_lines(lines: &[&str]) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(*line)).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_scratch(&self) -> bool {
+        self.scratch
+    }
+
     pub fn insert(&mut self, at: &Pos, c: char) {
+        if !self.can_change() {
+            return;
+        }
         if at.y > self.rows.len() {
             return;
         }
-        self.dirty = true;
+        let old_len = self.rows.len();
+        let replaced = at.y..cmp::min(at.y.saturating_add(1), old_len);
+        self.record_change(replaced, if c == '\n' { 2 } else { 1 });
         if c == '\n' {
-            if at.x == self.rows[at.y].len() {
-                self.insert_newline_at_end(at.y);
-            } else {
-                self.insert_newline(at);
+            match self.rows.get(at.y) {
+                Some(row) if at.x == row.len() => self.insert_newline_at_end(at.y),
+                Some(_) => self.insert_newline(at),
+                // the virtual row past the end becomes a real one
+                None => self.rows.insert_many(at.y, [Row::default(), Row::default()]),
             }
         } else if at.y == self.rows.len() {
             let mut row = Row::default();
@@ -48,9 +187,28 @@ impl Document {
         } else {
             #[allow(clippy::indexing_slicing)]
             let row = &mut self.rows[at.y];
+            // past the end of the row (see virtualedit) spaces fill the gap
+            while row.len() < at.x {
+                row.insert(row.len(), ' ');
+            }
             row.insert(at.x, c);
         }
-        self.unhighlight_rows(at.y);
+        self.adjust_folds(at.y, old_len);
+    }
+
+    // insert `text` (possibly multiline) at `at`, returns the position
+    // right after it
+    pub fn insert_str(&mut self, at: &Pos, text: &str) -> Pos {
+        let mut pos = *at;
+        for c in text.chars() {
+            self.insert(&pos, c);
+            if c == '\n' {
+                pos = Pos { x: 0, y: pos.y.saturating_add(1) };
+            } else {
+                pos.x = pos.x.saturating_add(1);
+            }
+        }
+        pos
     }
 
     // more efficient (w/o split)
@@ -78,36 +236,830 @@ impl Document {
 
     #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
     pub fn delete(&mut self, at: &Pos) {
+        if !self.can_change() {
+            return;
+        }
         let len = self.rows.len();
         if at.y >= len {
             return;
         }
-        self.dirty = true;
-        if at.x == self.rows[at.y].len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
+        let joined = at.x == self.rows[at.y].len() && at.y + 1 < len;
+        self.record_change(at.y..at.y + if joined { 2 } else { 1 }, 1);
+        if joined {
+            if let Some(next_row) = self.rows.remove(at.y + 1) {
+                let row = &mut self.rows[at.y];
+                row.append(&next_row);
+            }
         } else {
             let row = &mut self.rows[at.y];
             row.delete(at.x);
         }
-        self.unhighlight_rows(at.y);
+        self.adjust_folds(at.y, len);
+    }
+
+    // delete the text from `start` up to (excluding) `end`, joining the rows
+    // in between, an `end` past the last row means the end of the document
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn delete_range(&mut self, start: &Pos, end: &Pos) {
+        if !self.can_change() {
+            return;
+        }
+        let len = self.rows.len();
+        if start.y >= len || (end.y, end.x) <= (start.y, start.x) {
+            return;
+        }
+        let (end_y, end_x) = if end.y >= len {
+            (len - 1, self.rows[len - 1].len())
+        } else {
+            (end.y, end.x)
+        };
+        self.record_change(start.y..end_y + 1, 1);
+        let tail = self.rows[end_y].split(end_x);
+        if end_y > start.y {
+            self.rows.remove_range(start.y + 1..end_y + 1);
+        }
+        let row = &mut self.rows[start.y];
+        row.split(start.x);
+        row.append(&tail);
+        self.adjust_folds(start.y, len);
+    }
+
+    // insert `text` as whole rows (split on '\n') before row `at`
+    pub fn insert_rows(&mut self, at: usize, text: &str) {
+        if !self.can_change() {
+            return;
+        }
+        let old_len = self.rows.len();
+        let at = cmp::min(at, old_len);
+        self.record_change(at..at, text.split('\n').count());
+        self.rows.insert_many(at, text.split('\n').map(Row::from));
+        self.adjust_folds(at, old_len);
+    }
+
+    // remove the spaces and tabs at the end of the rows, returns the number
+    // of rows changed
+    pub fn trim_trailing(&mut self) -> usize {
+        if !self.can_change() {
+            return 0;
+        }
+        let trailing: Vec<(usize, usize, usize)> = self
+            .rows
+            .iter()
+            .enumerate()
+            .filter_map(|(y, row)| {
+                let kept = row.as_str().trim_end_matches(|c| c == ' ' || c == '\t');
+                let kept = kept.graphemes(true).count();
+                Some((y, kept, row.len())).filter(|_| kept < row.len())
+            })
+            .collect();
+        for &(y, kept, len) in &trailing {
+            self.delete_range(&Pos { x: kept, y }, &Pos { x: len, y });
+        }
+        trailing.len()
+    }
+
+    // replace the leading spaces of row `y` by `indent` spaces
+    pub fn set_indent(&mut self, y: usize, indent: usize) {
+        let current = match self.rows.get(y) {
+            Some(row) => row.indent(),
+            None => return,
+        };
+        if indent < current {
+            self.delete_range(&Pos { x: indent, y }, &Pos { x: current, y });
+        } else if indent > current {
+            self.insert_str(&Pos { x: 0, y }, &" ".repeat(indent.saturating_sub(current)));
+        }
+    }
+
+    // replace the first (or with `global` every) match of `regex` in row
+    // `y` by what `expand` gives for it, a '\n' in the replacement breaks the
+    // row. Returns the number of replacements
+    pub fn substitute<F>(&mut self, y: usize, regex: &Regex, global: bool, mut expand: F) -> usize
+    where
+        F: FnMut(&Captures) -> String,
+    {
+        let text = match self.rows.get(y) {
+            Some(row) => row.as_str(),
+            None => return 0,
+        };
+        let mut count: usize = 0;
+        // a limit of 0 replaces every match
+        let limit = if global { 0 } else { 1 };
+        let text = regex
+            .replacen(text, limit, |captures: &Captures| {
+                count = count.saturating_add(1);
+                expand(captures)
+            })
+            .into_owned();
+        if count == 0 || !self.can_change() {
+            return 0;
+        }
+        self.record_change(y..y.saturating_add(1), text.split('\n').count());
+        let old_len = self.rows.len();
+        self.rows.remove(y);
+        self.rows.insert_many(y, text.split('\n').map(Row::from));
+        self.adjust_folds(y, old_len);
+        count
+    }
+
+    // the text from `start` up to (excluding) `end`, rows are joined with
+    // '\n', an `end` past the last row means the end of the document
+    pub fn text(&self, start: &Pos, end: &Pos) -> String {
+        let mut text = String::new();
+        for (y, row) in self.rows.iter().enumerate().skip(start.y) {
+            if y > end.y {
+                break;
+            }
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            text.push_str(&row.substring(from, to));
+            if y < end.y && y.saturating_add(1) < self.rows.len() {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    // delete the rows from `start` to `end` (inclusive)
+    pub fn delete_rows(&mut self, start: usize, end: usize) {
+        if !self.can_change() {
+            return;
+        }
+        if start >= self.rows.len() || end < start {
+            return;
+        }
+        let old_len = self.rows.len();
+        let end = cmp::min(end, old_len.saturating_sub(1));
+        self.record_change(start..end.saturating_add(1), 0);
+        self.rows.remove_range(start..end.saturating_add(1));
+        self.adjust_folds(start, old_len);
+    }
+
+    // the distinct words starting with `prefix`, in order of appearance
+    // going forward from row `y` and wrapping around the end
+    pub fn words_with_prefix(&self, prefix: &str, y: usize) -> Vec<String> {
+        let mut words: Vec<String> = Vec::new();
+        let y = cmp::min(y, self.rows.len());
+        for row in self.rows.iter().skip(y).chain(self.rows.iter().take(y)) {
+            for word in row.words() {
+                if word.len() > prefix.len()
+                    && word.starts_with(prefix)
+                    && !words.iter().any(|w| w == word)
+                {
+                    words.push(word.to_owned());
+                }
+            }
+        }
+        words
+    }
+
+    // the rows (start, end) of the paragraph around row `y`, a paragraph is
+    // delimited by blank rows
+    pub fn paragraph(&self, y: usize) -> Option<(usize, usize)> {
+        if self.rows.get(y).map_or(true, Row::is_blank) {
+            return None;
+        }
+        let mut start = y;
+        while start > 0 && !self.rows[start.saturating_sub(1)].is_blank() {
+            start = start.saturating_sub(1);
+        }
+        let mut end = y;
+        while self.rows.get(end.saturating_add(1)).map_or(false, |row| !row.is_blank()) {
+            end = end.saturating_add(1);
+        }
+        Some((start, end))
+    }
+
+    // `ip` / `ap`, the rows (start, end) of the paragraph, or the blank
+    // rows, at row `y`. `ap` adds the blank rows that follow a paragraph
+    // (those before it at the end of the file) or the paragraph that
+    // follows blank rows
+    pub fn paragraph_object(&self, y: usize, around: bool) -> Option<(usize, usize)> {
+        let last = self.rows.len().checked_sub(1)?;
+        if y > last {
+            return None;
+        }
+        let (start, end) = self.blank_run(y);
+        if !around {
+            return Some((start, end));
+        }
+        if end < last {
+            Some((start, self.blank_run(end.saturating_add(1)).1))
+        } else if start > 0 && !self.rows[y].is_blank() {
+            Some((self.blank_run(start.saturating_sub(1)).0, end))
+        } else {
+            Some((start, end))
+        }
+    }
+
+    // the rows around `y` that are all blank, or all not blank
+    fn blank_run(&self, y: usize) -> (usize, usize) {
+        let blank = self.rows.get(y).map_or(true, Row::is_blank);
+        let same = |row: &Row| row.is_blank() == blank;
+        let mut start = y;
+        while start > 0 && self.rows.get(start.saturating_sub(1)).map_or(false, same) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = y;
+        while self.rows.get(end.saturating_add(1)).map_or(false, same) {
+            end = end.saturating_add(1);
+        }
+        (start, end)
+    }
+
+    // `{` / `}`, the first blank row before/after the paragraph at (or
+    // following) row `y`, or the first/last row when there is none
+    pub fn paragraph_boundary(&self, y: usize, direction: SearchDirection) -> usize {
+        let last = self.rows.len().saturating_sub(1);
+        let is_blank = |y: usize| self.rows.get(y).map_or(true, Row::is_blank);
+        let mut y = cmp::min(y, last);
+        if direction == SearchDirection::Forward {
+            while y < last && is_blank(y) {
+                y = y.saturating_add(1);
+            }
+            while y < last && !is_blank(y) {
+                y = y.saturating_add(1);
+            }
+        } else {
+            while y > 0 && is_blank(y) {
+                y = y.saturating_sub(1);
+            }
+            while y > 0 && !is_blank(y) {
+                y = y.saturating_sub(1);
+            }
+        }
+        y
+    }
+
+    // `(` / `)`, the start of the sentence before/after `at`. A sentence
+    // ends with '.', '!' or '?' (maybe followed by closing brackets or
+    // quotes) and whitespace, blank rows separate sentences too
+    pub fn sentence_start(&self, at: &Pos, direction: SearchDirection) -> Pos {
+        let mut pos = *at;
+        loop {
+            let next = if direction == SearchDirection::Forward {
+                self.next_pos(pos)
+            } else {
+                self.prev_pos(pos)
+            };
+            match next {
+                Some(next) => pos = next,
+                None => return pos,
+            }
+            if self.is_sentence_start(pos) {
+                return pos;
+            }
+        }
+    }
+
+    fn is_sentence_start(&self, pos: Pos) -> bool {
+        let row = match self.rows.get(pos.y) {
+            Some(row) => row,
+            None => return false,
+        };
+        if row.is_empty() {
+            return true;
+        }
+        if row.char_at(pos.x).map_or(true, char::is_whitespace) {
+            return false;
+        }
+        let mut after_whitespace = false;
+        let mut prev = pos;
+        loop {
+            if prev.x == 0 {
+                // the row break counts as whitespace
+                if prev.y == 0 || self.rows[prev.y.saturating_sub(1)].is_blank() {
+                    return true;
+                }
+                after_whitespace = true;
+            }
+            prev = match self.prev_pos(prev) {
+                Some(prev) => prev,
+                None => return true,
+            };
+            match self.rows[prev.y].char_at(prev.x) {
+                Some(c) if c.is_whitespace() => after_whitespace = true,
+                Some(c) if after_whitespace && c.is_ascii_punctuation() => {
+                    let mut c = c;
+                    while matches!(c, ')' | ']' | '"' | '\'') && prev.x > 0 {
+                        prev.x = prev.x.saturating_sub(1);
+                        c = self.rows[prev.y].char_at(prev.x).unwrap_or(' ');
+                    }
+                    return matches!(c, '.' | '!' | '?');
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    // the position of the next/previous character, empty rows have one
+    // position too
+    fn next_pos(&self, pos: Pos) -> Option<Pos> {
+        let len = self.rows.get(pos.y)?.len();
+        if pos.x.saturating_add(1) < len {
+            Some(Pos { x: pos.x.saturating_add(1), y: pos.y })
+        } else if pos.y.saturating_add(1) < self.rows.len() {
+            Some(Pos { x: 0, y: pos.y.saturating_add(1) })
+        } else {
+            None
+        }
+    }
+
+    fn prev_pos(&self, pos: Pos) -> Option<Pos> {
+        if pos.x > 0 {
+            return Some(Pos { x: pos.x.saturating_sub(1), y: pos.y });
+        }
+        let y = pos.y.checked_sub(1)?;
+        let len = self.rows.get(y)?.len();
+        Some(Pos { x: len.saturating_sub(1), y })
+    }
+
+    // re-break the rows from `start` to `end` (inclusive) at word boundaries
+    // so that each fits in `width` columns where possible, paragraphs are
+    // reflowed independently and keep the indentation of their first row.
+    // returns the index of the last reflowed row
+    #[allow(clippy::integer_arithmetic)]
+    pub fn reflow(&mut self, start: usize, end: usize, width: usize) -> usize {
+        if !self.can_change() {
+            return start;
+        }
+        if start >= self.rows.len() || end < start {
+            return start;
+        }
+        let end = cmp::min(end, self.rows.len() - 1);
+        let mut reflowed = Vec::new();
+        let mut y = start;
+        while y <= end {
+            if self.rows[y].is_blank() {
+                reflowed.push(Row::from(self.rows[y].as_str()));
+                y += 1;
+                continue;
+            }
+            let first = self.rows[y].as_str();
+            let indent = &first[..first.len() - first.trim_start().len()];
+            let mut line = String::from(indent);
+            while y <= end && !self.rows[y].is_blank() {
+                for word in self.rows[y].as_str().split_whitespace() {
+                    if line.len() == indent.len() {
+                        line.push_str(word);
+                    } else if line.width() + 1 + word.width() > width {
+                        reflowed.push(Row::from(&line[..]));
+                        line = format!("{}{}", indent, word);
+                    } else {
+                        line.push(' ');
+                        line.push_str(word);
+                    }
+                }
+                y += 1;
+            }
+            reflowed.push(Row::from(&line[..]));
+        }
+        let last = start + reflowed.len() - 1;
+        let old_len = self.rows.len();
+        self.record_change(start..end + 1, reflowed.len());
+        self.rows.remove_range(start..end + 1);
+        self.rows.insert_many(start, reflowed);
+        self.folds.retain(|fold| fold.end < start || fold.start > end);
+        self.adjust_folds(start, old_len);
+        last
+    }
+
+    // `:set bomb` / `:set nobomb`, whether the file is saved with a UTF-8
+    // byte order mark
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom;
+    }
+
+    // ======================== modifiable ========================
+
+    pub fn set_modifiable(&mut self, modifiable: bool) {
+        self.locked = !modifiable;
+    }
+
+    pub fn is_modifiable(&self) -> bool {
+        !self.locked
+    }
+
+    fn can_change(&mut self) -> bool {
+        if self.locked {
+            self.refused_change = true;
+        }
+        !self.locked
+    }
+
+    // whether a change was refused since the last call
+    pub fn take_refused_change(&mut self) -> bool {
+        mem::take(&mut self.refused_change)
+    }
+
+    // ======================== undo ========================
+
+    // the modifications until the next call are undone together
+    pub fn start_change(&mut self, cursor: Pos) {
+        self.history.start_change(cursor);
+    }
+
+    // called before rows `replaced` are replaced by `len` rows: the rows
+    // they were are kept for undo, and they are highlighted again (so are
+    // the rows after them whose multiline comment state the change altered)
+    fn record_change(&mut self, replaced: Range<usize>, len: usize) {
+        let rows = &self.rows;
+        self.history.record(replaced.clone(), len, || {
+            replaced.clone().filter_map(|y| rows.get(y)).map(|row| row.as_str().to_owned()).collect()
+        });
+        for y in replaced.clone() {
+            if let Some(row) = self.rows.get_mut(y) {
+                row.is_highlighted = false;
+            }
+        }
+        self.highlighted = cmp::min(self.highlighted, replaced.start);
+        self.version = self.version.wrapping_add(1);
+    }
+
+    // returns where to put the cursor, None when there is nothing to undo
+    pub fn undo(&mut self, cursor: Pos) -> Option<Pos> {
+        if !self.can_change() {
+            return Some(cursor);
+        }
+        let (history, restore) = self.restore();
+        history.undo(cursor, restore)
+    }
+
+    pub fn redo(&mut self, cursor: Pos) -> Option<Pos> {
+        if !self.can_change() {
+            return Some(cursor);
+        }
+        let (history, restore) = self.restore();
+        history.redo(cursor, restore)
+    }
+
+    // `g-` / `g+`, to the state of the document before / after the current
+    // one in time, whichever branch of the undo tree it is on
+    pub fn step_in_time(&mut self, forward: bool, cursor: Pos) -> Option<Pos> {
+        if !self.can_change() {
+            return Some(cursor);
+        }
+        let (history, restore) = self.restore();
+        history.step_in_time(forward, cursor, restore)
+    }
+
+    // the history, and what puts rows back for it: `(at, len, rows)` puts
+    // `rows` in place of rows `at..at + len` and gives the rows taken out
+    fn restore(&mut self) -> (&mut History, impl FnMut(usize, usize, Vec<String>) -> Vec<String> + '_) {
+        self.folds.clear();
+        self.version = self.version.wrapping_add(1);
+        let (rows, highlighted) = (&mut self.rows, &mut self.highlighted);
+        let restore = move |at: usize, len: usize, text: Vec<String>| {
+            let taken = (at..at.saturating_add(len))
+                .filter_map(|y| rows.get(y))
+                .map(|row| row.as_str().to_owned())
+                .collect();
+            rows.remove_range(at..cmp::min(at.saturating_add(len), rows.len()));
+            rows.insert_many(at, text.iter().map(|line| Row::from(line.as_str())));
+            *highlighted = cmp::min(*highlighted, at);
+            taken
+        };
+        (&mut self.history, restore)
+    }
+
+    // ======================== folds ========================
+    // folds are made of indentation: a row and the more indented rows that
+    // follow it (blank rows in between included), or of markers: the rows
+    // from one with `{{{` to the one with its `}}}` (or the last row)
+
+    // the closed folds are dropped, they may not be folds of `method`
+    pub fn set_foldmethod(&mut self, method: FoldMethod) {
+        if method != self.foldmethod {
+            self.foldmethod = method;
+            self.folds.clear();
+        }
+    }
+
+    fn block(&self, y: usize) -> Option<Fold> {
+        match self.foldmethod {
+            FoldMethod::Indent => self.indent_block(y),
+            FoldMethod::Marker => self.marker_block(y),
+        }
+    }
+
+    fn marker_block(&self, y: usize) -> Option<Fold> {
+        let (open, close) = markers(self.rows.get(y)?);
+        if open <= close {
+            return None;
+        }
+        let mut depth = open.saturating_sub(close);
+        let mut end = self.rows.len().saturating_sub(1);
+        for (i, row) in self.rows.iter().enumerate().skip(y.saturating_add(1)) {
+            let (open, close) = markers(row);
+            depth = depth.saturating_add(open);
+            if close >= depth {
+                end = i;
+                break;
+            }
+            depth = depth.saturating_sub(close);
+        }
+        if end > y {
+            Some(Fold { start: y, end })
+        } else {
+            None
+        }
+    }
+
+    fn indent_block(&self, y: usize) -> Option<Fold> {
+        let indent = self.rows.get(y)?.index_first_char();
+        let mut end = y;
+        for (i, row) in self.rows.iter().enumerate().skip(y.saturating_add(1)) {
+            if row.is_blank() {
+                continue;
+            }
+            if row.index_first_char() <= indent {
+                break;
+            }
+            end = i;
+        }
+        if end > y {
+            Some(Fold { start: y, end })
+        } else {
+            None
+        }
+    }
+
+    // every block, found in one pass over the rows (`block` for each row
+    // would walk the rows below it again)
+    fn blocks(&self) -> Vec<Fold> {
+        let mut blocks = match self.foldmethod {
+            FoldMethod::Indent => self.indent_blocks(),
+            FoldMethod::Marker => self.marker_blocks(),
+        };
+        blocks.sort_by_key(|block| block.start);
+        blocks
+    }
+
+    // a block ends before the first non-blank row that is not more indented
+    // than its first row
+    fn indent_blocks(&self) -> Vec<Fold> {
+        // the first rows of the blocks still open, by indent
+        let mut open: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut last = None; // the last non-blank row so far
+        let mut blocks = Vec::new();
+        let ending = |starts: BTreeMap<usize, Vec<usize>>, end: Option<usize>| {
+            starts
+                .into_values()
+                .flatten()
+                .filter_map(move |start| end.filter(|&end| end > start).map(|end| Fold { start, end }))
+        };
+        for (y, row) in self.rows.iter().enumerate() {
+            let indent = row.index_first_char();
+            if !row.is_blank() {
+                blocks.extend(ending(open.split_off(&indent), last));
+                last = Some(y);
+            }
+            open.entry(indent).or_default().push(y);
+        }
+        blocks.extend(ending(open, last));
+        blocks
+    }
+
+    // `level` counts the `{{{` not closed yet, a block ends on the row that
+    // takes it back to the level before its first row
+    fn marker_blocks(&self) -> Vec<Fold> {
+        // the first rows of the blocks still open, with the level before
+        // them (increasing)
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        let mut level: usize = 0;
+        let mut blocks = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            let (opens, closes) = markers(row);
+            let new_level = level.saturating_add(opens).saturating_sub(closes);
+            while let Some((start, _)) = open.pop_if(|&mut (_, before)| before >= new_level) {
+                blocks.push(Fold { start, end: y });
+            }
+            if opens > closes {
+                open.push((y, level));
+            }
+            level = new_level;
+        }
+        let end = self.rows.len().saturating_sub(1);
+        blocks.extend(open.into_iter().filter(|&(start, _)| end > start).map(|(start, _)| Fold { start, end }));
+        blocks
+    }
+
+    // the block around row `y`: the one it starts, or the one of the closest
+    // row above that is less indented
+    fn enclosing_block(&self, y: usize, starting_at_y: bool) -> Option<Fold> {
+        if starting_at_y {
+            if let Some(block) = self.block(y) {
+                return Some(block);
+            }
+        }
+        if self.foldmethod == FoldMethod::Marker {
+            return (0..y).rev().filter_map(|i| self.marker_block(i)).find(|block| block.end >= y);
+        }
+        let indent = self.rows.get(y)?.index_first_char();
+        let header = (0..y)
+            .rev()
+            .find(|&i| !self.rows[i].is_blank() && self.rows[i].index_first_char() < indent)?;
+        self.indent_block(header)
+    }
+
+    // `zc`, closing the fold at a row that is already in a closed fold
+    // closes the enclosing one
+    pub fn close_fold(&mut self, y: usize) -> bool {
+        let fold = match self.fold_at(y) {
+            Some(fold) => self.enclosing_block(fold.start, false),
+            None => self.enclosing_block(y, true),
+        };
+        if let Some(fold) = fold {
+            self.folds.retain(|f| f.start < fold.start || f.end > fold.end);
+            self.folds.push(fold);
+            return true;
+        }
+        false
+    }
+
+    // `zo`, opens the outermost closed fold at row `y`
+    pub fn open_fold(&mut self, y: usize) -> bool {
+        if let Some(fold) = self.fold_at(y) {
+            self.folds.retain(|f| *f != fold);
+            return true;
+        }
+        false
+    }
+
+    // `zR`
+    pub fn open_all_folds(&mut self) {
+        self.folds.clear();
+    }
+
+    // `zM`, every fold is closed, the nested ones too
+    pub fn close_all_folds(&mut self) {
+        self.folds = self.blocks();
+    }
+
+    // the outermost closed fold containing row `y`
+    pub fn fold_at(&self, y: usize) -> Option<Fold> {
+        self.folds
+            .iter()
+            .filter(|fold| fold.start <= y && y <= fold.end)
+            .max_by_key(|fold| fold.end.saturating_sub(fold.start))
+            .copied()
+    }
+
+    // rows hidden in a closed fold are represented by the fold's first row
+    pub fn visible_row(&self, y: usize) -> usize {
+        self.fold_at(y).map_or(y, |fold| fold.start)
+    }
+
+    pub fn next_visible_row(&self, y: usize) -> usize {
+        self.fold_at(y).map_or(y, |fold| fold.end).saturating_add(1)
+    }
+
+    pub fn prev_visible_row(&self, y: usize) -> usize {
+        self.visible_row(y.saturating_sub(1))
+    }
+
+    // number of screen rows used by rows from..to
+    pub fn visible_rows_between(&self, from: usize, to: usize) -> usize {
+        if self.folds.is_empty() {
+            return to.saturating_sub(from);
+        }
+        let mut count = 0;
+        let mut y = from;
+        while y < to {
+            y = self.next_visible_row(y);
+            count += 1;
+        }
+        count
+    }
+
+    // keep the folds on their rows when rows were added or removed at row
+    // `at`, the folds around the removed rows are opened
+    fn adjust_folds(&mut self, at: usize, old_len: usize) {
+        let len = self.rows.len();
+        if len == old_len {
+            return;
+        }
+        let removed_end = at.saturating_add(old_len.saturating_sub(len));
+        self.folds.retain(|fold| fold.end < at || fold.start > removed_end);
+        for fold in &mut self.folds {
+            if fold.start > at {
+                fold.start = fold.start.saturating_add(len).saturating_sub(old_len);
+                fold.end = fold.end.saturating_add(len).saturating_sub(old_len);
+            }
+        }
     }
 
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(filename) = &self.filename {
             let mut file = fs::File::create(filename)?;
             self.filetype = FileType::from(&filename[..]);
+            let mut saved = String::new();
+            if self.bom {
+                file.write_all("\u{feff}".as_bytes())?;
+            }
             for row in &mut self.rows {
                 file.write_all(row.as_bytes())?;
                 file.write_all(b"\n")?;
+                saved.push_str(row.as_str());
+                saved.push('\n');
             }
-            self.dirty = false;
+            drop(file);
+            self.mtime = modified(filename);
+            self.history.mark_saved();
+            // the undo history is only a convenience, failing to keep it
+            // does not fail the save
+            if let Some(path) = undo_file(filename) {
+                if let Some(dir) = path.parent() {
+                    let _ = fs::create_dir_all(dir);
+                }
+                let _ = fs::write(path, self.history.serialize(history::hash(&saved)));
+            }
+            self.original = Some(saved.split_terminator('\n').map(str::to_owned).collect());
+            self.changes = None;
         }
         Ok(())
     }
 
-    pub fn find(&self, query: &str, at: &Pos, direction: SearchDirection) -> Option<Pos> {
+    // whether another program wrote the file since we read or saved it
+    pub fn changed_on_disk(&self) -> bool {
+        match (&self.filename, self.mtime) {
+            (Some(filename), Some(mtime)) => modified(filename).map_or(false, |time| time > mtime),
+            _ => false,
+        }
+    }
+
+    pub fn find(&self, query: &Regex, at: &Pos, direction: SearchDirection) -> Option<Pos> {
+        self.find_with(at, direction, |row, x| match direction {
+            SearchDirection::Forward => row.find_regex(query, x).map(|(range, _)| range.start),
+            SearchDirection::Backward => row.rfind_regex(query, x),
+        })
+    }
+
+    // like `find` but `word` only matches whole words
+    pub fn find_word(&self, word: &str, at: &Pos, direction: SearchDirection) -> Option<Pos> {
+        self.find_with(at, direction, |row, x| row.find_word(word, x, direction))
+    }
+
+    // `%`, the bracket matching the first one of `pairs` at or after `at`
+    // in its row, nested pairs of the same kind are skipped
+    pub fn matching_pair(&self, at: &Pos, pairs: &[(char, char)]) -> Option<Pos> {
+        let row = self.rows.get(at.y)?;
+        let (x, c) = (at.x..row.len())
+            .filter_map(|x| row.char_at(x).map(|c| (x, c)))
+            .find(|(_, c)| pairs.iter().any(|&(open, close)| *c == open || *c == close))?;
+        let &(open, close) = pairs.iter().find(|&&(open, close)| c == open || c == close)?;
+        let forward = c == open;
+        let mut depth = 0_usize;
+        let mut pos = Pos { x, y: at.y };
+        loop {
+            let row = self.rows.get(pos.y)?;
+            match row.char_at(pos.x) {
+                Some(c) if (c == open && forward) || (c == close && !forward) => depth = depth.saturating_add(1),
+                Some(c) if c == open || c == close => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Some(pos);
+                    }
+                }
+                _ => (),
+            }
+            pos = if forward {
+                if pos.x.saturating_add(1) < row.len() {
+                    Pos { x: pos.x.saturating_add(1), y: pos.y }
+                } else {
+                    Pos { x: 0, y: pos.y.checked_add(1)? }
+                }
+            } else if pos.x > 0 {
+                Pos { x: pos.x.saturating_sub(1), y: pos.y }
+            } else {
+                let y = pos.y.checked_sub(1)?;
+                Pos { x: self.rows.get(y)?.len().saturating_sub(1), y }
+            };
+        }
+    }
+
+    // the first row that defines `name` with one of the filetype's
+    // definition keywords (`fn name`, `let mut name`...), at the name
+    pub fn find_definition(&self, name: &str) -> Option<Pos> {
+        let keywords: Vec<String> = self
+            .filetype
+            .definition_keywords()
+            .iter()
+            .map(|keyword| regex::escape(keyword))
+            .collect();
+        let pattern = format!(r"\b(?:{})\s+(?:mut\s+)?{}\b", keywords.join("|"), regex::escape(name));
+        let regex = Regex::new(&pattern).ok()?;
+        let len = name.graphemes(true).count();
+        self.rows.iter().enumerate().find_map(|(y, row)| {
+            let (range, _) = row.find_regex(&regex, 0)?;
+            Some(Pos { x: range.end.saturating_sub(len), y })
+        })
+    }
+
+    fn find_with<F>(&self, at: &Pos, direction: SearchDirection, find: F) -> Option<Pos>
+    where
+        F: Fn(&Row, usize) -> Option<usize>,
+    {
         if at.y > self.rows.len() {
             return None;
         }
@@ -125,7 +1077,7 @@ impl Document {
 
         for _ in start..end {
             if let Some(row) = self.rows.get(pos.y) {
-                if let Some(x) = row.find(&query, pos.x, direction) {
+                if let Some(x) = find(row, pos.x) {
                     pos.x = x;
                     return Some(pos);
                 }
@@ -143,29 +1095,51 @@ impl Document {
         None
     }
 
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
-            } else {
-                self.rows.len()
-            }
-        } else {
-            self.rows.len()
+    // highlight the rows from `from` up to `until`. Highlighting goes on
+    // from the last row highlighted in a known state when it is before
+    // `from`, so that the multiline comment state carried into the visible
+    // rows is right (e.g. when scrolling into a long comment). Rows already
+    // highlighted in the same state only give the state they cached.
+    // Matches of `word` are only highlighted in `word_rows` (inclusive)
+    // when given
+    pub fn highlight(
+        &mut self,
+        word: &Option<Regex>,
+        word_rows: Option<(usize, usize)>,
+        spell: Option<&Dictionary>,
+        rainbow: bool,
+        from: usize,
+        until: Option<usize>,
+    ) {
+        let until = until.map_or(self.rows.len(), |until| cmp::min(until.saturating_add(1), self.rows.len()));
+        let start = cmp::min(from, self.highlighted);
+        let (mut start_with_comment, mut brackets) = match start.checked_sub(1).and_then(|y| self.rows.get(y)) {
+            Some(row) => (row.ends_in_comment(), row.brackets_after().to_vec()),
+            None => (false, Vec::new()),
         };
-        for row in &mut self.rows[..until] {
+        for y in start..until {
+            let row = match self.rows.get_mut(y) {
+                Some(row) => row,
+                None => break,
+            };
+            let in_word_rows = word_rows.map_or(true, |(start, end)| start <= y && y <= end);
             start_with_comment = row.highlight(
                 self.filetype.options(),
-                word,
-                start_with_comment);
+                if in_word_rows { word } else { &None },
+                spell,
+                start_with_comment,
+                if rainbow { Some(&mut brackets) } else { None },
+            );
         }
+        self.highlighted = cmp::max(self.highlighted, until);
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        for row in self.rows.iter_mut().skip(start.saturating_sub(1)) {
+    // the rows from `start` to the end are highlighted again
+    pub fn unhighlight_rows(&mut self, start: usize) {
+        for row in self.rows.iter_mut().skip(start) {
             row.is_highlighted = false;
         }
+        self.highlighted = cmp::min(self.highlighted, start);
     }
 
     pub fn row(&self, index: usize) -> Option<&Row> {
@@ -180,11 +1154,263 @@ impl Document {
         self.filetype.name()
     }
 
+    // whether the content differs from what was last saved (or opened),
+    // undoing back to it is not a modification
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        self.history.is_modified()
     }
 
     pub fn is_empty(&self) -> bool {
         self.rows.is_empty()
     }
+
+    // how each row differs from the file on disk as of the last
+    // `update_changes`, None when there's nothing to compare with
+    pub fn changes(&self) -> Option<&[Option<Change>]> {
+        self.original.as_ref()?;
+        self.changes.as_ref().map(|(_, changes)| changes.as_slice())
+    }
+
+    // compare the rows with the file on disk again if they were modified
+    // since the last time
+    pub fn update_changes(&mut self) {
+        if self.changes.as_ref().map_or(true, |(version, _)| *version != self.version) {
+            self.changes = self.compare_with_original().map(|changes| (self.version, changes));
+        }
+    }
+
+    // rows are aligned with the file (longest common subsequence), so
+    // inserting a row doesn't mark every row after it
+    fn compare_with_original(&self) -> Option<Vec<Option<Change>>> {
+        let original = self.original.as_ref()?;
+        let row = |y: usize| self.rows.get(y).map(Row::as_str);
+        let same = |y: usize, line: usize| row(y).is_some() && row(y) == original.get(line).map(String::as_str);
+        let (rows, lines) = (self.rows.len(), original.len());
+        let mut prefix = 0;
+        while prefix < cmp::min(rows, lines) && same(prefix, prefix) {
+            prefix = prefix.saturating_add(1);
+        }
+        let mut suffix = 0;
+        while suffix < cmp::min(rows, lines).saturating_sub(prefix)
+            && same(rows.saturating_sub(suffix).saturating_sub(1), lines.saturating_sub(suffix).saturating_sub(1))
+        {
+            suffix = suffix.saturating_add(1);
+        }
+        let mut changes = vec![None; rows];
+        let middle = prefix..rows.saturating_sub(suffix);
+        let removed = prefix..lines.saturating_sub(suffix);
+        let matched = if middle.len().saturating_mul(removed.len()) <= MAX_DIFF_CELLS {
+            common_rows(middle.len(), removed.len(), |y, line| {
+                same(middle.start.saturating_add(y), removed.start.saturating_add(line))
+            })
+        } else {
+            // too big to align, compare the rows in order
+            Vec::new()
+        };
+        // between two matched rows, the first changed rows replace the removed
+        // lines and the others are new
+        let mut y = 0;
+        let mut line = 0;
+        for (next_y, next_line) in matched.into_iter().chain([(middle.len(), removed.len())]) {
+            let replaced = next_line.saturating_sub(line);
+            for (i, change) in changes
+                .iter_mut()
+                .skip(middle.start.saturating_add(y))
+                .take(next_y.saturating_sub(y))
+                .enumerate()
+            {
+                *change = Some(if i < replaced { Change::Modified } else { Change::Added });
+            }
+            y = next_y.saturating_add(1);
+            line = next_line.saturating_add(1);
+        }
+        Some(changes)
+    }
+
+    // whether there is something to compare with
+    pub fn has_original(&self) -> bool {
+        self.original.is_some()
+    }
+
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+}
+
+// the pairs (row, line) of a longest common subsequence of `rows` rows and
+// `lines` lines, in order. `same` compares a row with a line
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+fn common_rows<F: Fn(usize, usize) -> bool>(rows: usize, lines: usize, same: F) -> Vec<(usize, usize)> {
+    // longest[y][line], the length for rows y.. and lines line..
+    let width = lines + 1;
+    let mut longest = vec![0u32; (rows + 1) * width];
+    for y in (0..rows).rev() {
+        for line in (0..lines).rev() {
+            longest[y * width + line] = if same(y, line) {
+                longest[(y + 1) * width + line + 1] + 1
+            } else {
+                cmp::max(longest[(y + 1) * width + line], longest[y * width + line + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut y, mut line) = (0, 0);
+    while y < rows && line < lines {
+        if same(y, line) {
+            pairs.push((y, line));
+            y += 1;
+            line += 1;
+        } else if longest[(y + 1) * width + line] >= longest[y * width + line + 1] {
+            y += 1;
+        } else {
+            line += 1;
+        }
+    }
+    pairs
+}
+
+// the `{{{` and `}}}` fold markers in `row`
+fn markers(row: &Row) -> (usize, usize) {
+    let text = row.as_str();
+    (text.matches("{{{").count(), text.matches("}}}").count())
+}
+
+fn modified(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename).and_then(|metadata| metadata.modified()).ok()
+}
+
+// ~/.rum/undo/ with the absolute path of the file, '/' replaced by '%'
+fn undo_file(filename: &str) -> Option<PathBuf> {
+    let home = env::var_os("HOME")?;
+    let path = fs::canonicalize(filename).ok()?;
+    let name = path.to_string_lossy().replace('/', "%");
+    Some(PathBuf::from(home).join(".rum").join("undo").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Type;
+
+    fn rust_document(lines: &[&str]) -> Document {
+        Document {
+            rows: lines.iter().map(|line| Row::from(*line)).collect(),
+            filetype: FileType::from("test.rs"),
+            ..Document::default()
+        }
+    }
+
+    fn highlight(document: &mut Document) {
+        document.highlight(&None, None, None, false, 0, None);
+    }
+
+    fn in_comment(document: &Document, y: usize) -> bool {
+        document
+            .row(y)
+            .map_or(false, |row| row.highlighting().iter().all(|hl| *hl == Type::MultilineComment))
+    }
+
+    #[test]
+    fn closing_a_comment_recolors_the_rows_below() {
+        let mut document = rust_document(&["/* open", "let a = 1;", "let b = 2; */", "let c = 3;"]);
+        highlight(&mut document);
+        assert!(in_comment(&document, 1));
+        assert!(!in_comment(&document, 3));
+        document.insert_str(&Pos { x: 7, y: 0 }, " */");
+        highlight(&mut document);
+        assert!(!in_comment(&document, 1));
+        assert!(!in_comment(&document, 2));
+    }
+
+    #[test]
+    fn opening_a_comment_recolors_the_rows_below() {
+        let mut document = rust_document(&["let a = 1;", "let b = 2;", "let c = 3; */", "let d = 4;"]);
+        highlight(&mut document);
+        assert!(!in_comment(&document, 1));
+        document.insert_str(&Pos { x: 0, y: 0 }, "/* ");
+        highlight(&mut document);
+        assert!(in_comment(&document, 1));
+        assert!(in_comment(&document, 2));
+        assert!(!in_comment(&document, 3));
+    }
+
+    #[test]
+    fn scrolling_into_a_long_comment_shows_it_commented() {
+        let mut lines = vec!["/* a long comment"];
+        lines.extend(vec!["inside"; 200]);
+        lines.push("*/ let after = 1;");
+        let mut document = rust_document(&lines);
+        document.highlight(&None, None, None, false, 0, Some(20));
+        document.highlight(&None, None, None, false, 130, Some(150));
+        assert!(in_comment(&document, 150));
+        document.highlight(&None, None, None, false, 190, Some(201));
+        assert!(document.row(201).map_or(false, |row| row.highlighting().last() != Some(&Type::MultilineComment)));
+    }
+
+    #[test]
+    fn trim_trailing_counts_only_changed_rows() {
+        let mut document = Document::from_lines(&["a  ", "b", "c\t"]);
+        document.set_modifiable(false);
+        assert_eq!(document.trim_trailing(), 0);
+        assert_eq!(document.row(0).map(Row::as_str), Some("a  "));
+        document.set_modifiable(true);
+        assert_eq!(document.trim_trailing(), 2);
+        assert_eq!(document.row(2).map(Row::as_str), Some("c"));
+    }
+
+    // cargo test --release -- --ignored --nocapture dd_in_a_million_rows
+    #[test]
+    #[ignore]
+    fn dd_in_a_million_rows_benchmark() {
+        let lines: Vec<String> = (0..1_000_000).map(|i| format!("let row_{} = {}; // row", i, i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut document = rust_document(&lines);
+        // the first highlighting walks down from the first row, and the
+        // first edit moves the gap of the row buffer there
+        highlight_screen(&mut document, 500_000);
+        document.start_change(Pos { x: 0, y: 500_000 });
+        document.delete_rows(500_000, 500_000);
+        document.undo(Pos::default());
+        let started = std::time::Instant::now();
+        for _ in 0..1000 {
+            document.start_change(Pos { x: 0, y: 500_000 });
+            document.delete_rows(500_000, 500_000);
+            highlight_screen(&mut document, 500_000);
+        }
+        println!("dd in the middle of 1M rows: {:?}", started.elapsed() / 1000);
+        let started = std::time::Instant::now();
+        for _ in 0..1000 {
+            document.undo(Pos::default());
+            highlight_screen(&mut document, 500_000);
+        }
+        println!("u in the middle of 1M rows: {:?}", started.elapsed() / 1000);
+        assert_eq!(document.len(), 1_000_000);
+    }
+
+    fn highlight_screen(document: &mut Document, from: usize) {
+        document.highlight(&None, None, None, false, from, Some(from + 50));
+    }
+
+    fn closed_folds(lines: &[&str], method: FoldMethod) -> Vec<(usize, usize)> {
+        let mut document = Document::from_lines(lines);
+        document.set_foldmethod(method);
+        document.close_all_folds();
+        // the same as the block of each row
+        let blocks: Vec<_> = (0..document.len()).filter_map(|y| document.block(y)).collect();
+        assert!(blocks == document.folds);
+        document.folds.iter().map(|fold| (fold.start, fold.end)).collect()
+    }
+
+    #[test]
+    fn close_all_indent_folds() {
+        let lines = ["a", "  b", "    c", "", "  d", "", "e", "", "  f", "g"];
+        // a blank row starts a block of the more indented rows below it
+        assert_eq!(closed_folds(&lines, FoldMethod::Indent), [(0, 4), (1, 2), (3, 4), (6, 8), (7, 8)]);
+    }
+
+    #[test]
+    fn close_all_marker_folds() {
+        let lines = ["a {{{", "b {{{ {{{", "c }}}", "d }}}", "e }}}", "f }}}", "g {{{", "h"];
+        assert_eq!(closed_folds(&lines, FoldMethod::Marker), [(0, 4), (1, 3), (6, 7)]);
+    }
 }
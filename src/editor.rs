@@ -1,31 +1,98 @@
-use crate::{document::Document, Row, Terminal};
+use crate::highlighting::{
+    COLOR_CURSORLINE, COLOR_FOLD_BG, COLOR_FOLD_FG, COLOR_SIGN_ADDED, COLOR_SIGN_MODIFIED,
+};
+use crate::spell::Dictionary;
+use crate::substitute::Substitute;
+use crate::quickfix::Quickfix;
+use crate::finder::{self, Finder};
+use crate::increment;
+use crate::tags::{self, Address};
+use crate::window::{Rect, View, Windows};
+use crate::document::{Change, Document, Fold, FoldMethod};
+use crate::config::CursorStyle;
+use crate::{Config, Input, RenderOptions, Row, Terminal};
+use std::cmp;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
 use std::time::Duration;
 use std::time::Instant;
+use regex::{Regex, RegexBuilder};
 use termion::color;
 use termion::event::Key;
-use termion::cursor;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-const TABSIZE: usize = 4;
+const MODE_FG_COLOR: color::Rgb = color::Rgb(46, 52, 64);
+const MODE_NORMAL_COLOR: color::Rgb = color::Rgb(136, 192, 208);
+const MODE_INSERT_COLOR: color::Rgb = color::Rgb(163, 190, 140);
+const MODE_VISUAL_COLOR: color::Rgb = color::Rgb(208, 135, 112);
+const MODE_REPLACE_COLOR: color::Rgb = color::Rgb(191, 97, 106);
+const DEFAULT_TEXTWIDTH: usize = 79;
+// normal mode keys that are simply repeated when given a count, other
+// commands read the count themselves
+const REPEATABLE_KEYS: [Key; 7] = [
+    Key::Char('h'),
+    Key::Char('j'),
+    Key::Char('k'),
+    Key::Char('l'),
+    Key::Char('x'),
+    Key::Char('\n'),
+    Key::Backspace,
+];
+const AUTOPAIRS: [(char, char); 5] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+const SHOWCMD_WIDTH: usize = 11;
+const FINDER_ROWS: usize = 10; // matches shown by `:Files`
+const FINDER_DEPTH: usize = 8; // directories searched below the working one
+const RESIZE_POLL: Duration = Duration::from_millis(100); // how often a resize is looked for while waiting for a key
+const BELL_DURATION: Duration = Duration::from_millis(100);
 const VERSION: &str = env!["CARGO_PKG_VERSION"];
+// the commands completed on the command line
+const COMMANDS: [&str; 25] = [
+    "ascii", "cc", "cNext", "close", "cnext", "cprevious", "e", "edit", "enew", "Files", "grep", "make",
+    "marks", "put", "q", "registers", "set", "split", "substitute", "TrimWhitespace", "vsplit", "w", "wq",
+    "x", "xit",
+];
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 pub enum Mode {
     Normal,
     Visual,
+    VisualLine,
     Insert,
+    Replace,
 }
 
 impl Mode {
     fn to_string(&self) -> String {
         match self {
-            Mode::Normal => "-- Normal --".to_owned(),
-            Mode::Visual => "-- Visual --".to_owned(),
-            Mode::Insert => "-- Insert --".to_owned(),
+            Mode::Normal => "NORMAL".to_owned(),
+            Mode::Visual => "-- VISUAL --".to_owned(),
+            Mode::VisualLine => "-- VISUAL LINE --".to_owned(),
+            Mode::Insert => "-- INSERT --".to_owned(),
+            Mode::Replace => "-- REPLACE --".to_owned(),
+        }
+    }
+
+    // background of the mode in the status bar
+    fn color(self) -> color::Rgb {
+        match self {
+            Mode::Normal => MODE_NORMAL_COLOR,
+            Mode::Visual | Mode::VisualLine => MODE_VISUAL_COLOR,
+            Mode::Insert => MODE_INSERT_COLOR,
+            Mode::Replace => MODE_REPLACE_COLOR,
         }
     }
+
+    fn is_visual(self) -> bool {
+        self == Mode::Visual || self == Mode::VisualLine
+    }
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -34,12 +101,53 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, PartialEq)]
 pub struct Pos {
     pub x: usize,
     pub y: usize,
 }
 
+// a visual selection, kept after leaving visual mode for `gv`
+#[derive(Clone, Copy)]
+struct Selection {
+    anchor: Pos,
+    cursor: Pos,
+    mode: Mode,
+}
+
+// keyword completion in insert mode, the word being completed starts at
+// `start` and `index` is the candidate currently inserted (None: the prefix)
+struct Completion {
+    start: Pos,
+    prefix: String,
+    candidates: Vec<String>,
+    index: Option<usize>,
+}
+
+// Tab completion on the command line, the completed word starts at byte
+// `start` of the command and `index` is the candidate currently in it
+struct Wildmenu {
+    start: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+// the document a scratch buffer (`:registers`, `:marks`) is shown over, and
+// where the cursor was in it
+struct Hidden {
+    document: Document,
+    cursor: Pos,
+    offset: Pos,
+}
+
+// the text of the last delete or yank, `linewise` text is made of whole
+// rows joined with '\n'
+#[derive(Default)]
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
 pub struct StatusMessage {
     text: String,
     time: Instant,
@@ -60,38 +168,118 @@ pub struct Editor {
     terminal: Terminal,
     cursor_pos: Pos,
     offset: Pos,
+    windows: Windows, // the views of the other windows on the document
+    view: Rect,       // the text area of the current window (or the one being drawn)
+    other_window: bool, // drawing a window other than the current one
+    scrollbind: bool,   // the current window scrolls together with the others that have it
     document: Document,
     status_message: StatusMessage,
-    highlighted_word: Option<String>, // used for searching
+    bell: Option<Instant>, // when the flash of the visual bell ends
+    pasted: Option<String>, // a paste read where a key was expected, handled next
+    highlighted_word: Option<Regex>, // used for searching
+    highlighted_rows: Option<(usize, usize)>, // where highlighted_word is shown, None is everywhere
+    config: Config,
+    dictionary: Option<Dictionary>, // loaded on the first `:set spell`
+    visual_anchor: Pos, // the fixed end of the selection in visual mode
+    last_selection: Option<Selection>,
+    completion: Option<Completion>,
+    wildmenu: Option<Wildmenu>,
+    finder: Option<Finder>, // the files listed while `:Files` prompts
+    cursors: Vec<Pos>, // the other cursors of multi-cursor editing (`Ctrl-N`)
+    tag_stack: Vec<(Option<String>, Pos)>, // where `Ctrl-]` jumped from
+    marks: HashMap<char, Pos>, // set with `m`, in the current document
+    alternate: Option<(String, Pos)>, // the file edited before, for `Ctrl-6`
+    hidden: Option<Hidden>, // under the scratch buffer being shown
+    git_branch: Option<String>, // of the working directory, read again on save
+    // in replace mode, the graphemes overwritten so far (None where text was
+    // added), restored by Backspace
+    replaced: Vec<Option<String>>,
+    // the count of `3ihello` and whether each repeat goes on a new row (`o`
+    // / `O`), None when what's typed can't be repeated (e.g. the cursor moved)
+    insert_repeat: Option<(usize, bool)>,
+    inserted: String, // typed since entering insert mode
+    insert_start: Pos, // where insert mode was entered (or the cursor last moved to in it)
+    count: usize, // count typed before the current normal mode command
+    pending_keys: String, // keys of an unfinished command, shown like Vim's showcmd
+    register: Register,
+    insert_normal: bool, // running a normal mode command from insert mode (`Ctrl-O`)
+    last_substitute: Option<Substitute>, // repeated by `&` and `g&`
+    quickfix: Quickfix, // locations from the output of `:make`
 }
 
 impl Editor {
     pub fn default() -> Self {
         let mut init_status = String::from("");
-        let args: Vec<String> = env::args().collect();
-        let document = if let Some(filename) = args.get(1) {
-            let doc = Document::open(filename);
-            if let Ok(doc) = doc {
-                doc
-            } else {
-                init_status = format!("ERR: Failed to open file: {}", filename);
-                Document::default()
+        let args: Vec<String> = env::args().skip(1).collect();
+        let lossy = args.iter().any(|arg| arg == "-b");
+        let document = if let Some(filename) = args.iter().find(|arg| !arg.starts_with('-')) {
+            match Document::open(filename, lossy) {
+                Ok(doc) => {
+                    if doc.is_lossy() {
+                        init_status = format!("WARN: {} is not valid UTF-8, invalid bytes replaced", filename);
+                    }
+                    doc
+                }
+                Err(err) if err.kind() == ErrorKind::InvalidData => {
+                    init_status = format!("ERR: {} is not valid UTF-8 (use `rum -b` to open it anyway)", filename);
+                    Document::default()
+                }
+                Err(_) => {
+                    init_status = format!("ERR: Failed to open file: {}", filename);
+                    Document::default()
+                }
             }
         } else {
             Document::default()
         };
+        #[allow(clippy::expect_used)]
+        let terminal = Terminal::default().expect("Failed to initialize terminal");
+        Self::new(terminal, document, init_status)
+    }
 
-        Self {
+    fn new(terminal: Terminal, document: Document, init_status: String) -> Self {
+        let mut editor = Self {
             mode: Mode::Normal,
             quit: false,
-            #[allow(clippy::expect_used)]
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            terminal,
             cursor_pos: Pos::default(),
             document,
             offset: Pos::default(),
+            windows: Windows::default(),
+            view: Rect::default(),
+            other_window: false,
+            scrollbind: false,
             status_message: StatusMessage::from(init_status),
+            bell: None,
+            pasted: None,
             highlighted_word: None,
-        }
+            highlighted_rows: None,
+            config: Config::default(),
+            dictionary: None,
+            visual_anchor: Pos::default(),
+            last_selection: None,
+            completion: None,
+            wildmenu: None,
+            finder: None,
+            cursors: Vec::new(),
+            tag_stack: Vec::new(),
+            marks: HashMap::new(),
+            alternate: None,
+            hidden: None,
+            git_branch: git_branch(),
+            replaced: Vec::new(),
+            insert_repeat: None,
+            inserted: String::new(),
+            insert_start: Pos::default(),
+            count: 0,
+            pending_keys: String::new(),
+            register: Register::default(),
+            insert_normal: false,
+            last_substitute: None,
+            quickfix: Quickfix::default(),
+        };
+        editor.layout();
+        editor
     }
 
     pub fn run(&mut self) {
@@ -111,25 +299,92 @@ impl Editor {
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        match self.mode {
-            Mode::Normal => self.normal_process_keypress()?,
-            Mode::Insert => self.insert_process_keypress()?,
-            Mode::Visual => (),
+        let offset_y = self.offset.y;
+        let input = match self.pasted.take() {
+            Some(text) => Input::Paste(text),
+            None => self.read_input()?,
         };
+        match input {
+            Input::Paste(text) => self.insert_paste(&text),
+            Input::Key(key) => match self.mode {
+                Mode::Normal => self.normal_process_keypress(key)?,
+                Mode::Insert => self.insert_process_keypress(key)?,
+                Mode::Replace => self.replace_process_keypress(key)?,
+                Mode::Visual | Mode::VisualLine => self.visual_process_keypress(key)?,
+            },
+        }
+        if self.document.take_refused_change() {
+            self.set_status_message("Cannot make changes, 'modifiable' is off");
+            self.bell();
+        }
         self.scroll();
+        if self.scrollbind {
+            self.scroll_other_windows(offset_y);
+        }
         Ok(())
     }
 
+    // the next key or paste, the screen is drawn again meanwhile if the
+    // terminal is resized (there is no SIGWINCH handling, the size is polled)
+    fn read_input(&mut self) -> Result<Input, std::io::Error> {
+        loop {
+            let timeout = self.bell.map_or(RESIZE_POLL, |end| {
+                cmp::min(RESIZE_POLL, end.saturating_duration_since(Instant::now()))
+            });
+            if let Some(input) = self.terminal.read_input_timeout(timeout)? {
+                return Ok(input);
+            }
+            // the status bar is drawn normally again once the bell is over
+            let bell_over = self.bell.map_or(false, |end| Instant::now() >= end);
+            if bell_over {
+                self.bell = None;
+            }
+            if bell_over || self.terminal.is_resized() {
+                self.refresh_screen()?;
+            }
+        }
+    }
+
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        let input = self.read_input()?;
+        Ok(self.input_key(input))
+    }
+
+    // a paste where a key is expected cancels what the key was for (it's
+    // `Key::Null` then, see read_pending_key), and is handled after that
+    fn input_key(&mut self, input: Input) -> Key {
+        match input {
+            Input::Key(key) => key,
+            Input::Paste(text) => {
+                self.pasted = Some(text);
+                Key::Null
+            }
+        }
+    }
+
     fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String)
+        C: FnMut(&mut Self, &Input, &String)
     {
         let mut result = String::new();
         loop {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            let input = self.read_input()?;
+            let key = match &input {
+                Input::Key(key) => *key,
+                Input::Paste(text) => {
+                    result.extend(text.chars().filter(|c| !c.is_control()));
+                    Key::Null
+                }
+            };
+            if key != Key::Char('\t') && key != Key::BackTab {
+                self.wildmenu = None;
+            }
             match key {
+                Key::Char('\t') | Key::BackTab if prompt == ":" => {
+                    self.complete_command(&mut result, key == Key::Char('\t'));
+                }
                 Key::Backspace => result.truncate(result.len().saturating_sub(1)),
                 Key::Char('\n') => break,
                 Key::Char(c) => {
@@ -137,14 +392,16 @@ impl Editor {
                         result.push(c);
                     }
                 }
-                Key::Esc => {
+                Key::Esc | Key::Ctrl('c') => {
+                    self.finder = None;
                     result.truncate(0);
                     break;
                 }
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, &input, &result);
         }
+        self.wildmenu = None;
         self.status_message = StatusMessage::from(String::new());
         if result.is_empty() {
             return Ok(None);
@@ -152,133 +409,526 @@ impl Editor {
         Ok(Some(result))
     }
 
+    // Tab (or Shift-Tab backwards) replaces the word before the end of `cmd`
+    // by the next candidate: a command name, or a path after `:e`
+    fn complete_command(&mut self, cmd: &mut String, forward: bool) {
+        if let Some(menu) = &mut self.wildmenu {
+            let len = menu.candidates.len();
+            menu.index = if forward {
+                menu.index.saturating_add(1).checked_rem(len).unwrap_or(0)
+            } else {
+                menu.index.checked_sub(1).unwrap_or(len.saturating_sub(1))
+            };
+        } else {
+            let (start, mut candidates) = command_candidates(cmd);
+            if candidates.is_empty() {
+                self.bell();
+                return;
+            }
+            // a single candidate is simply completed, so that the next Tab
+            // continues from it (e.g. into a directory)
+            if candidates.len() == 1 {
+                cmd.truncate(start);
+                cmd.push_str(&candidates.remove(0));
+                return;
+            }
+            let index = if forward { 0 } else { candidates.len().saturating_sub(1) };
+            self.wildmenu = Some(Wildmenu { start, candidates, index });
+        }
+        if let Some(menu) = &self.wildmenu {
+            cmd.truncate(menu.start);
+            cmd.push_str(&menu.candidates[menu.index]);
+        }
+    }
+
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
+        // the terminal may have been resized since the last frame, in which
+        // case everything drawn so far is garbage
+        if self.terminal.update_size(self.status_rows().saturating_add(self.message_rows()))? {
+            Terminal::clear_screen();
+            self.layout();
+            self.scroll();
+        }
         Terminal::cursor_hide();
         Terminal::cursor_pos(&Pos::default());
         if self.quit {
             Terminal::clear_screen();
             println!("See ya");
         } else {
-            self.document.highlight(
-                &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
-            );
-            self.draw_rows();
-            self.draw_status_bar();
+            self.draw_other_windows();
+            self.layout();
+            self.draw_window();
+            self.draw_separators();
+            self.draw_finder();
             self.draw_message_bar();
+            self.draw_cursors();
             Terminal::cursor_pos(&Pos {
-                x: self.cursor_pos.x.saturating_sub(self.offset.x),
-                y: self.cursor_pos.y.saturating_sub(self.offset.y),
+                x: self
+                    .cursor_pos
+                    .x
+                    .saturating_sub(self.offset.x)
+                    .saturating_add(self.gutter_width())
+                    .saturating_add(self.view.x),
+                y: self
+                    .document
+                    .visible_rows_between(self.offset.y, self.cursor_pos.y)
+                    .saturating_add(self.view.y),
             });
         }
         Terminal::cursor_show();
         Terminal::flush()
     }
 
+    // the rows and status bar of the window in `view`
+    fn draw_window(&mut self) {
+        let spell = if self.config.spell {
+            self.dictionary.as_ref()
+        } else {
+            None
+        };
+        let last_visible_row = self.last_visible_row();
+        if self.gutter_width() > 0 {
+            self.document.update_changes();
+        }
+        self.document.highlight(
+            &self.highlighted_word,
+            self.highlighted_rows,
+            spell,
+            self.config.rainbow,
+            self.offset.y,
+            Some(last_visible_row),
+        );
+        self.draw_rows();
+        if self.status_rows() > 0 {
+            Terminal::cursor_pos(&Pos {
+                x: self.view.x,
+                y: self.view.y.saturating_add(self.view.height),
+            });
+            match &self.wildmenu {
+                Some(menu) if self.config.wildmenu && !self.other_window => self.draw_wildmenu(menu),
+                _ => self.draw_status_bar(),
+            }
+        }
+    }
+
+    // each other window is drawn by making it the current one for a moment
+    fn draw_other_windows(&mut self) {
+        let current = self.windows.current();
+        for (index, rect) in self.window_rects().into_iter().enumerate() {
+            let view = match self.windows.view(index) {
+                Some(view) if index != current => view,
+                _ => continue,
+            };
+            let own = (self.cursor_pos, self.offset, self.view, self.scrollbind);
+            self.cursor_pos = self.clamp_pos(view.cursor);
+            self.offset = view.offset;
+            self.view = self.text_rect(rect);
+            self.scrollbind = view.scrollbind;
+            self.other_window = true;
+            self.scroll();
+            self.draw_window();
+            self.windows.set_view(index, self.current_view());
+            self.other_window = false;
+            (self.cursor_pos, self.offset, self.view, self.scrollbind) = own;
+        }
+    }
+
+    // the best matches of `:Files` over the bottom of the windows, the
+    // selected one highlighted
+    fn draw_finder(&self) {
+        let finder = match &self.finder {
+            Some(finder) => finder,
+            None => return,
+        };
+        let width = self.terminal.size().width as usize;
+        let bottom = self.terminal.size().height.saturating_add(self.status_rows()) as usize;
+        let rows = cmp::min(FINDER_ROWS, bottom.saturating_sub(1));
+        let first = finder.index().saturating_sub(rows.saturating_sub(1));
+        let mut lines = vec![format!(" {}/{}", finder.matches().len(), finder.len())];
+        lines.extend(finder.matches().iter().skip(first).take(rows).map(|file| format!("  {}", file)));
+        for (i, line) in lines.iter().enumerate() {
+            Terminal::cursor_pos(&Pos {
+                x: 0,
+                y: bottom.saturating_sub(lines.len()).saturating_add(i),
+            });
+            if i > 0 && first.saturating_add(i).saturating_sub(1) == finder.index() {
+                Terminal::set_fg_color(MODE_FG_COLOR);
+                Terminal::set_bg_color(MODE_NORMAL_COLOR);
+            } else {
+                Terminal::set_fg_color(STATUS_FG_COLOR);
+                Terminal::set_bg_color(STATUS_BG_COLOR);
+            }
+            let line: String = line.chars().take(width).collect();
+            print!("{}{}", line, " ".repeat(width.saturating_sub(line.width())));
+        }
+        Terminal::reset_bg_color();
+        Terminal::reset_fg_color();
+    }
+
+    // the column right of each window that has another one beside it
+    fn draw_separators(&self) {
+        let width = self.terminal.size().width as usize;
+        Terminal::set_fg_color(STATUS_FG_COLOR);
+        Terminal::set_bg_color(STATUS_BG_COLOR);
+        for rect in self.window_rects() {
+            let x = rect.x.saturating_add(rect.width);
+            if x >= width {
+                continue;
+            }
+            for y in rect.y..rect.y.saturating_add(rect.height) {
+                Terminal::cursor_pos(&Pos { x, y });
+                print!("│");
+            }
+        }
+        Terminal::reset_bg_color();
+        Terminal::reset_fg_color();
+    }
+
+    // the area of each window on the screen, with its status bar
+    fn window_rects(&self) -> Vec<Rect> {
+        let size = self.terminal.size();
+        self.windows.rects(Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            height: size.height.saturating_add(self.status_rows()) as usize,
+        })
+    }
+
+    // the area of a window without its status bar
+    fn text_rect(&self, rect: Rect) -> Rect {
+        Rect {
+            height: rect.height.saturating_sub(self.status_rows() as usize),
+            ..rect
+        }
+    }
+
+    // update `view` for the current window, after the windows or the
+    // terminal changed
+    fn layout(&mut self) {
+        if let Some(rect) = self.window_rects().get(self.windows.current()) {
+            self.view = self.text_rect(*rect);
+        }
+    }
+
+    fn current_view(&self) -> View {
+        View {
+            cursor: self.cursor_pos,
+            offset: self.offset,
+            scrollbind: self.scrollbind,
+        }
+    }
+
+    // with `incsearch` the cursor moves to the match while typing, otherwise
+    // only once the query is entered
     fn search(&mut self) {
         let old_pos = self.cursor_pos;
         let mut direction = SearchDirection::Forward;
+        let mut found = false;
         let query = self
             .prompt(
                 "/",
             |editor, key, query| {
+                if !editor.config.incsearch {
+                    return;
+                }
                 let mut moved: bool = false;
                 match key {
-                    Key::Char('n') => {
+                    Input::Key(Key::Char('n')) => {
                         direction = SearchDirection::Forward;
                         editor.move_cursor(Key::Right);
                         moved = true;
                     },
-                    Key::Char('N') => {
+                    Input::Key(Key::Char('N')) => {
                         direction = SearchDirection::Backward;
                     },
                     _ => direction = SearchDirection::Forward,
                 }
-                if let Some(pos) =
-                    editor
-                        .document
-                        .find(query, &editor.cursor_pos, direction)
+                let regex = editor.search_regex(query);
+                if let Some(pos) = regex
+                    .as_ref()
+                    .and_then(|regex| editor.find_wrapping(regex, &editor.cursor_pos, direction))
                         {
                             editor.cursor_pos = pos;
                             editor.scroll();
-                        } else if moved {
-                            editor.move_cursor(Key::Left);
+                            found = true;
+                        } else {
+                            if moved {
+                                editor.move_cursor(Key::Left);
+                            }
+                            found = false;
                         }
-                        editor.highlighted_word = Some(query.to_string());
+                        editor.highlighted_word = regex;
             }).unwrap_or(None);
 
+            if !self.config.incsearch {
+                // the match incsearch would have moved to
+                if let Some(pos) = query
+                    .as_deref()
+                    .and_then(|query| self.search_regex(query))
+                    .and_then(|regex| self.find_wrapping(&regex, &old_pos, SearchDirection::Forward))
+                {
+                    self.cursor_pos = pos;
+                    self.scroll();
+                    found = true;
+                }
+            }
             if query.is_none() {
                 self.cursor_pos = old_pos;
                 self.scroll();
+            } else if !found {
+                self.set_status_message("Pattern not found");
+                self.bell();
             }
             self.highlighted_word = None;
     }
 
-    fn draw_welcome_messages(&self) {
-        self.process_and_print_welcome_msg(format!("Rum Editor -- version {}", VERSION));
-
-        Terminal::clear_current_line();
-        println!("~\r");
+    // the match of `regex` from `at`, wrapping around the end (or the start)
+    // of the document
+    fn find_wrapping(&self, regex: &Regex, at: &Pos, direction: SearchDirection) -> Option<Pos> {
+        let wrap_at = if direction == SearchDirection::Forward {
+            Pos::default()
+        } else {
+            let last = self.document.len().saturating_sub(1);
+            let end = self.document.row(last).map_or(0, Row::len);
+            Pos { x: end, y: last }
+        };
+        self.document
+            .find(regex, at, direction)
+            .or_else(|| self.document.find(regex, &wrap_at, direction))
+    }
 
-        self.process_and_print_welcome_msg(format!("A Vim-like Editor Completely in Rust"));
-        self.process_and_print_welcome_msg(format!("Rum is permanently free and open-source"));
+    // `/` searches for the query literally
+    fn search_regex(&self, query: &str) -> Option<Regex> {
+        RegexBuilder::new(&regex::escape(query))
+            .case_insensitive(self.config.ignore_case(query))
+            .build()
+            .ok()
+            .filter(|_| !query.is_empty())
+    }
 
-        Terminal::clear_current_line();
-        println!("~\r");
+    // `*` / `#`, search the whole word under the cursor, wrapping around
+    // the end of the document
+    fn search_word_under_cursor(&mut self, direction: SearchDirection) {
+        let Pos { x, y } = self.cursor_pos;
+        let (word, start) = match self.document.row(y) {
+            Some(row) => match row.word_at(x) {
+                Some(word) => (word, row.word_start_before(x)),
+                None => {
+                    self.set_status_message("No word under cursor");
+                    self.bell();
+                    return;
+                }
+            },
+            None => return,
+        };
+        let (at, wrap_at) = if direction == SearchDirection::Forward {
+            (Pos { x: start.saturating_add(1), y }, Pos::default())
+        } else {
+            let last = self.document.len().saturating_sub(1);
+            let end = self.document.row(last).map_or(0, Row::len);
+            (Pos { x: start, y }, Pos { x: end, y: last })
+        };
+        let found = self
+            .document
+            .find_word(&word, &at, direction)
+            .or_else(|| self.document.find_word(&word, &wrap_at, direction));
+        if let Some(pos) = found {
+            self.cursor_pos = pos;
+        }
+        self.highlighted_word = Regex::new(&regex::escape(&word)).ok();
+    }
 
-        self.process_and_print_welcome_msg(format!("Use: <:q> \t to <Exit>"));
-        self.process_and_print_welcome_msg(format!("Use: <:wq> \t to <Save>"));
+    // `gd`, go to where the word under the cursor is defined, guessed from
+    // the filetype's definition keywords, or else to its first occurrence
+    fn goto_definition(&mut self) {
+        let Pos { x, y } = self.cursor_pos;
+        let word = match self.document.row(y).and_then(|row| row.word_at(x)) {
+            Some(word) => word,
+            None => {
+                self.set_status_message("No identifier under cursor");
+                self.bell();
+                return;
+            }
+        };
+        let found = self
+            .document
+            .find_definition(&word)
+            .or_else(|| self.document.find_word(&word, &Pos::default(), SearchDirection::Forward));
+        if let Some(pos) = found {
+            self.cursor_pos = pos;
+        }
+        self.highlighted_word = Regex::new(&regex::escape(&word)).ok();
     }
 
-    fn process_and_print_welcome_msg(&self, msg: String) {
-        let width = self.terminal.size().width as usize;
+    fn welcome_messages() -> Vec<String> {
+        vec![
+            format!("Rum Editor -- version {}", VERSION),
+            String::new(),
+            "A Vim-like Editor Completely in Rust".to_owned(),
+            "Rum is permanently free and open-source".to_owned(),
+            String::new(),
+            "Use: <:q>    to <Exit>".to_owned(),
+            "Use: <:wq>   to <Save>".to_owned(),
+        ]
+    }
 
-        let len = msg.len();
+    // center the message by display width, a message that does not fit
+    // in the terminal is hidden and only the `~` is left
+    fn process_welcome_msg(&self, msg: &str) -> String {
+        let width = self.view.width;
+        let len = msg.width();
+        if len.saturating_add(1) > width {
+            return "~".to_owned();
+        }
 
         #[allow(clippy::integer_arithmetic, clippy::integer_division)]
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding.saturating_sub(1));
 
-        let mut processed_msg = format!("~{}{}", spaces, msg);
-        processed_msg.truncate(width);
-
-        Terminal::clear_current_line();
-        println!("{}\r", processed_msg);
+        format!("~{}{}", spaces, msg)
     }
 
-    fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
+    fn draw_row(&self, row: &Row, y: usize) {
+        let width = self.text_width();
         let start = self.offset.x;
         let end = start.saturating_add(width);
-        let row = row.render(start, end);
+        let opts = RenderOptions {
+            selected: self.selected_range(y, row),
+            background: if self.config.cursorline && y == self.cursor_pos.y {
+                Some(COLOR_CURSORLINE)
+            } else {
+                None
+            },
+            color_column: self.config.colorcolumn.checked_sub(1),
+            listchars: Some(self.config.listchars).filter(|_| self.config.list),
+        };
+        let row = row.render(start, end, &opts);
         println!("{}\r", row);
     }
 
+    // a closed fold is drawn as its first row's text and the number of rows
+    fn draw_fold(&self, row: &Row, fold: Fold) {
+        let width = self.text_width();
+        let text = row.as_str();
+        let indent = &text[..text.len().saturating_sub(text.trim_start().len())];
+        let mut summary = format!(
+            "{}+--{} lines: {} ",
+            indent,
+            fold.end.saturating_sub(fold.start).saturating_add(1),
+            text.trim()
+        );
+        summary = summary.chars().skip(self.offset.x).take(width).collect();
+        summary.push_str(&"-".repeat(width.saturating_sub(summary.chars().count())));
+        Terminal::set_fg_color(COLOR_FOLD_FG);
+        Terminal::set_bg_color(COLOR_FOLD_BG);
+        println!("{}\r", summary);
+        Terminal::reset_bg_color();
+        Terminal::reset_fg_color();
+    }
+
+    // the last document row on screen
+    fn last_visible_row(&self) -> usize {
+        let mut y = self.offset.y;
+        for _ in 1..self.view.height {
+            y = self.document.next_visible_row(y);
+        }
+        y
+    }
+
+    // the sign column is only shown while the buffer differs from the file
+    fn gutter_width(&self) -> usize {
+        if self.document.has_original() && self.document.is_dirty() {
+            2
+        } else {
+            0
+        }
+    }
+
+    // columns left for the text
+    fn text_width(&self) -> usize {
+        self.view.width.saturating_sub(self.gutter_width())
+    }
+
+    // `+` for added rows and `~` for modified ones
+    fn draw_sign(change: Option<Change>) {
+        match change {
+            Some(Change::Added) => {
+                Terminal::set_fg_color(COLOR_SIGN_ADDED);
+                print!("+ ");
+            }
+            Some(Change::Modified) => {
+                Terminal::set_fg_color(COLOR_SIGN_MODIFIED);
+                print!("~ ");
+            }
+            None => print!("  "),
+        }
+        Terminal::reset_fg_color();
+    }
+
     #[allow(clippy::integer_arithmetic, clippy::integer_division)]
     fn draw_rows(&self) {
-        let height = self.terminal.size().height;
+        let height = self.view.height;
+        let welcome = Self::welcome_messages();
+        // the welcome block is dropped entirely if the window is too short
+        let welcome_start = if welcome.len() < height {
+            (height - welcome.len()) / 2
+        } else {
+            height
+        };
+        let changes = if self.gutter_width() > 0 {
+            self.document.changes()
+        } else {
+            None
+        };
+        let mut y = self.offset.y;
         for term_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(term_row as usize))
-            {
-                self.draw_row(row);
-            } else if (term_row == height / 2) && self.document.is_empty() {
-                self.draw_welcome_messages();
+            let pos = Pos {
+                x: self.view.x,
+                y: self.view.y.saturating_add(term_row),
+            };
+            Terminal::cursor_pos(&pos);
+            // a window beside another one only clears its own columns
+            if self.view.width < self.terminal.size().width as usize {
+                print!("{}", " ".repeat(self.view.width));
+                Terminal::cursor_pos(&pos);
+            } else {
+                Terminal::clear_current_line();
+            }
+            let welcome_msg = term_row
+                .checked_sub(welcome_start)
+                .and_then(|index| welcome.get(index));
+            if let Some(row) = self.document.row(y) {
+                if let Some(changes) = changes {
+                    Self::draw_sign(changes.get(y).copied().flatten());
+                }
+                if let Some(fold) = self.document.fold_at(y) {
+                    self.draw_fold(row, fold);
+                } else {
+                    self.draw_row(row, y);
+                }
+                y = self.document.next_visible_row(y);
+            } else if let Some(msg) = welcome_msg.filter(|_| self.document.is_empty()) {
+                println!("{}\r", self.process_welcome_msg(msg));
             } else {
                 println!("~\r");
             }
         }
     }
 
-    fn draw_status_bar(&self) {
-        let mut filename = "[No Name]".to_owned();
-        let width = self.terminal.size().width as usize;
+    fn mode_status(&self) -> String {
+        if self.insert_normal {
+            return " -- (insert) -- ".to_owned();
+        }
+        if self.mode == Mode::Insert && self.config.paste {
+            return " -- INSERT (paste) -- ".to_owned();
+        }
+        format!(" {} ", self.mode.to_string())
+    }
+
+    // the status bar after the mode, `width` columns wide
+    fn status_bar_text(&self, width: usize) -> String {
+        let mut filename = if self.document.is_scratch() { "[Scratch]" } else { "[No Name]" }.to_owned();
 
         if let Some(name) = &self.document.filename {
             filename = name.clone();
@@ -293,39 +943,220 @@ impl Editor {
             ""
         };
 
-        let mode_status = self.mode.to_string();
-
         let line_status = format!(
             "line: {}/{}",
             self.cursor_pos.y.saturating_add(1),
             self.document.len()
         );
 
-        let file_status = format!(
+        let mut file_status = format!(
             "{}[{}]",
             filename,
             self.document.file_type()
         );
+        if let Some(branch) = self.git_branch.as_ref().filter(|_| self.config.gitbranch) {
+            file_status.push_str(&format!(" ({})", branch));
+        }
 
-        let mut status = format!("|{}| {} - {} {}", mode_status, file_status, line_status, dirty_status);
+        let mut status = format!(" {} - {} {}", file_status, line_status, dirty_status);
         status.push_str(&" ".repeat(width.saturating_sub(status.len())));
         status.truncate(width);
+        status
+    }
+
+    // the mode is only shown for the current window
+    fn draw_status_bar(&self) {
+        let mut width = self.view.width;
+        // the visual bell, the whole bar in inverted colors
+        if !self.other_window && self.bell.map_or(false, |end| Instant::now() < end) {
+            Terminal::set_fg_color(STATUS_BG_COLOR);
+            Terminal::set_bg_color(STATUS_FG_COLOR);
+            width = width.saturating_sub(self.mode_status().len());
+            println!("{}{}\r", self.mode_status(), self.status_bar_text(width));
+            Terminal::reset_bg_color();
+            Terminal::reset_fg_color();
+            return;
+        }
+        if !self.other_window {
+            Terminal::set_fg_color(MODE_FG_COLOR);
+            let mode = if self.insert_normal { Mode::Insert } else { self.mode };
+            Terminal::set_bg_color(mode.color());
+            print!("{}", self.mode_status());
+            width = width.saturating_sub(self.mode_status().len());
+        }
+        Terminal::set_fg_color(STATUS_FG_COLOR);
+        Terminal::set_bg_color(STATUS_BG_COLOR);
+        println!("{}\r", self.status_bar_text(width));
+        Terminal::reset_bg_color();
+        Terminal::reset_fg_color();
+    }
+
+    // the other cursors of multi-cursor editing, in inverted colors
+    fn draw_cursors(&self) {
+        let last_visible_row = self.last_visible_row();
+        let width = self.text_width();
+        for pos in &self.cursors {
+            if pos.y < self.offset.y || pos.y > last_visible_row || pos.x < self.offset.x {
+                continue;
+            }
+            let x = pos.x.saturating_sub(self.offset.x);
+            if x >= width {
+                continue;
+            }
+            let text = self
+                .document
+                .row(pos.y)
+                .and_then(|row| row.grapheme_at(pos.x))
+                .unwrap_or(" ");
+            Terminal::cursor_pos(&Pos {
+                x: x.saturating_add(self.gutter_width()).saturating_add(self.view.x),
+                y: self
+                    .document
+                    .visible_rows_between(self.offset.y, pos.y)
+                    .saturating_add(self.view.y),
+            });
+            Terminal::set_fg_color(MODE_FG_COLOR);
+            Terminal::set_bg_color(MODE_NORMAL_COLOR);
+            print!("{}", text);
+            Terminal::reset_bg_color();
+            Terminal::reset_fg_color();
+        }
+    }
 
+    // the completion candidates in place of the status bar, scrolled so that
+    // the current one is visible, `<` / `>` mark candidates out of view
+    fn draw_wildmenu(&self, menu: &Wildmenu) {
+        let width = self.view.width;
+        let item_width = |item: &String| item.width().saturating_add(2);
+        let mut first = 0;
+        while first < menu.index
+            && menu.candidates[first..=menu.index].iter().map(item_width).sum::<usize>() > width.saturating_sub(4)
+        {
+            first = first.saturating_add(1);
+        }
         Terminal::set_fg_color(STATUS_FG_COLOR);
         Terminal::set_bg_color(STATUS_BG_COLOR);
-        println!("{}\r", status);
+        let mut used: usize = 0;
+        if first > 0 {
+            print!("< ");
+            used = 2;
+        }
+        for (i, item) in menu.candidates.iter().enumerate().skip(first) {
+            if used.saturating_add(item_width(item)) > width.saturating_sub(2) {
+                print!(">");
+                used = used.saturating_add(1);
+                break;
+            }
+            if i == menu.index {
+                Terminal::set_fg_color(MODE_FG_COLOR);
+                Terminal::set_bg_color(MODE_NORMAL_COLOR);
+                print!("{}", item);
+                Terminal::set_fg_color(STATUS_FG_COLOR);
+                Terminal::set_bg_color(STATUS_BG_COLOR);
+            } else {
+                print!("{}", item);
+            }
+            print!("  ");
+            used = used.saturating_add(item_width(item));
+        }
+        println!("{}\r", " ".repeat(width.saturating_sub(used)));
         Terminal::reset_bg_color();
         Terminal::reset_fg_color();
     }
 
+    // signal that an action could not be performed, with `visualbell` the
+    // status bar is drawn in inverted colors for a moment (keys are still
+    // read meanwhile), otherwise it's a no-op
+    fn bell(&mut self) {
+        if self.config.visualbell {
+            self.bell = Instant::now().checked_add(BELL_DURATION);
+        }
+    }
+
+    // `laststatus`, without other windows 1 hides the status bar like 0,
+    // with them every window has one
+    fn status_rows(&self) -> u16 {
+        u16::from(self.config.laststatus >= 2 || self.windows.len() > 1)
+    }
+
+    // `cmdheight`, at least one row is always kept for the message bar
+    #[allow(clippy::cast_possible_truncation)]
+    fn message_rows(&self) -> u16 {
+        cmp::max(self.config.cmdheight, 1) as u16
+    }
+
     fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+        let width = self.terminal.size().width as usize;
+        let top = self.terminal.size().height.saturating_add(self.status_rows()) as usize;
+        let rows = self.message_rows() as usize;
         let msg = &self.status_message;
-        // only print status message within 5 sec
+        // only print status message within 5 sec, a long message wraps
+        // over the rows of the message bar and is cut at the last one
+        let mut lines = Vec::new();
         if Instant::now() - msg.time < Duration::new(5, 0) {
-            let mut text = msg.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+            let chars: Vec<char> = msg.text.chars().collect();
+            lines = chars
+                .chunks(cmp::max(width, 1))
+                .take(rows)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect();
+        }
+        for i in 0..rows {
+            Terminal::cursor_pos(&Pos { x: 0, y: top.saturating_add(i) });
+            Terminal::clear_current_line();
+            if let Some(line) = lines.get(i) {
+                print!("{}", line);
+            }
+        }
+        // the pending keys go in the bottom right corner
+        if !self.pending_keys.is_empty() {
+            let column = width.saturating_sub(SHOWCMD_WIDTH);
+            Terminal::cursor_pos(&Pos {
+                x: column,
+                y: top.saturating_add(rows).saturating_sub(1),
+            });
+            let keys: String = self
+                .pending_keys
+                .chars()
+                .rev()
+                .take(SHOWCMD_WIDTH.saturating_sub(1))
+                .collect::<Vec<char>>()
+                .into_iter()
+                .rev()
+                .collect();
+            print!("{}", keys);
+        }
+    }
+
+    // show `lines` in a scratch buffer in place of the document until `q`
+    // (or `:q`), for listings like `:registers`
+    fn show_list(&mut self, lines: &[String]) {
+        let document = Document::scratch(lines);
+        if self.hidden.is_some() {
+            self.document = document;
+        } else {
+            self.hidden = Some(Hidden {
+                document: mem::replace(&mut self.document, document),
+                cursor: self.cursor_pos,
+                offset: self.offset,
+            });
+        }
+        self.cursor_pos = Pos::default();
+        self.offset = Pos::default();
+        self.set_status_message("Press q to close");
+    }
+
+    // back to the document under the scratch buffer, false if there is none
+    fn close_scratch(&mut self) -> bool {
+        match self.hidden.take() {
+            Some(hidden) => {
+                self.document = hidden.document;
+                self.cursor_pos = self.clamp_pos(hidden.cursor);
+                self.offset = hidden.offset;
+                self.set_status_message("");
+                true
+            }
+            None => false,
         }
     }
 
@@ -339,17 +1170,17 @@ impl Editor {
         };
 
         match key {
-            Key::Up => y = y.saturating_sub(1),
+            Key::Up => y = self.document.prev_visible_row(y),
             Key::Down => {
                 if y < height {
-                    y = y.saturating_add(1);
+                    y = self.document.next_visible_row(y);
                 }
             }
             Key::Left => {
                 if x > 0 {
                     x -= 1;
                 } else if y > 0 {
-                    y -= 1;
+                    y = self.document.prev_visible_row(y);
                     if let Some(row) = self.document.row(y) {
                         x = row.len();
                     } else {
@@ -358,10 +1189,10 @@ impl Editor {
                 }
             }
             Key::Right => {
-                if x < width {
+                if x < width || (self.config.virtualedit && y < height) {
                     x += 1;
                 } else if y < height {
-                    y += 1;
+                    y = self.document.next_visible_row(y);
                     x = 0;
                 }
             }
@@ -375,32 +1206,56 @@ impl Editor {
             0
         };
 
-        if x > width {
+        if x > width && !self.config.virtualedit {
             x = width;
         }
 
         self.cursor_pos = Pos { x, y };
     }
 
+    #[allow(clippy::integer_division)]
     fn scroll(&mut self) {
+        // the cursor can not be inside a closed fold, it sits on its first row
+        self.cursor_pos.y = self.document.visible_row(self.cursor_pos.y);
         let Pos { x, y } = self.cursor_pos;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let width = self.text_width();
+        let height = self.view.height;
+        // columns kept visible on each side of the cursor, but none past the
+        // end of the row
+        let margin = cmp::min(self.config.sidescrolloff, width.saturating_sub(1) / 2);
+        let row_len = self.document.row(y).map_or(0, Row::len);
+        let right_margin = cmp::min(margin, row_len.saturating_sub(x));
+        let document = &self.document;
+        let offset = &mut self.offset;
 
+        offset.y = document.visible_row(offset.y);
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
             offset.y = y.saturating_sub(height).saturating_add(1);
         }
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        while document.visible_rows_between(offset.y, y) >= height {
+            offset.y = document.next_visible_row(offset.y);
+        }
+        if x < offset.x.saturating_add(margin) {
+            offset.x = x.saturating_sub(margin);
+        } else if x.saturating_add(right_margin) >= offset.x.saturating_add(width) {
+            offset.x = x
+                .saturating_add(right_margin)
+                .saturating_sub(width)
+                .saturating_add(1);
         }
     }
 
+    // with other windows only the current one is closed
     fn quit(&mut self, force: bool) {
+        if self.close_scratch() {
+            return;
+        }
+        if self.windows.len() > 1 {
+            self.close_window();
+            return;
+        }
         if self.document.is_dirty() && !force {
             self.set_status_message("File unsaved, use (:q! to force quit)");
             return;
@@ -408,36 +1263,548 @@ impl Editor {
         self.quit = true;
     }
 
-    fn save(&mut self) {
-        if self.document.filename.is_none() {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none() {
-                self.set_status_message("Save aborted");
-                return;
-            }
-            self.document.filename = new_name;
+    // `:x` / `ZZ`, unlike `:wq` the file is only written when modified
+    fn exit(&mut self) {
+        if !self.document.is_dirty() || self.save(false) {
+            self.quit(false);
         }
+    }
 
-        if self.document.save().is_ok() {
-            self.set_status_message("File saved successfully");
+    // `:e {file}`, make `filename` the current document, unsaved changes are
+    // only dropped with `force` (`:e!`)
+    fn edit(&mut self, filename: &str, force: bool) {
+        if !self.can_abandon(force) {
+            return;
+        }
+        let (document, is_new) = match Document::open(filename, false) {
+            Ok(document) => (document, false),
+            Err(err) if err.kind() == ErrorKind::NotFound => (Document::new_file(filename), true),
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                self.set_status_message(&format!("ERR: {} is not valid UTF-8 (use `rum -b` to open it anyway)", filename));
+                self.bell();
+                return;
+            }
+            Err(_) => {
+                self.set_status_message(&format!("ERR: Failed to open file: {}", filename));
+                self.bell();
+                return;
+            }
+        };
+        self.set_document(document);
+        if is_new {
+            self.set_status_message(&format!("\"{}\" [New]", filename));
         } else {
-            self.set_status_message("Failed to save file");
+            self.show_file_info();
         }
     }
 
-    fn change_mode(&mut self, mode: Mode) {
-        self.mode = mode;
-        match self.mode {
-            Mode::Insert => {
-                print!("{}", cursor::BlinkingBar);
-            },
-            Mode::Normal => {
-                print!("{}", cursor::BlinkingBlock);
-                self.normal_move_cursor('h');
+    // `Ctrl-6` / `:b#`, edit the alternate file again, back where the
+    // cursor was in it
+    fn edit_alternate(&mut self, force: bool) {
+        let (filename, pos) = match self.alternate.clone() {
+            Some(alternate) => alternate,
+            None => {
+                self.set_status_message("No alternate file");
+                self.bell();
+                return;
+            }
+        };
+        self.edit(&filename, force);
+        if self.document.filename.as_deref() == Some(filename.as_str()) {
+            self.cursor_pos = self.clamp_pos(pos);
+            self.show_file_info();
+        }
+    }
+
+    // `:Files`, pick a file under the working directory by typing parts of
+    // its path, Up / Down (or `Ctrl-P` / `Ctrl-N`) select another match,
+    // Enter edits the selected one
+    fn find_files(&mut self) -> Result<(), std::io::Error> {
+        self.finder = Some(Finder::new(".", FINDER_DEPTH));
+        self.prompt("Files: ", |editor, key, query| {
+            if let Some(finder) = &mut editor.finder {
+                match key {
+                    Input::Key(Key::Up | Key::Ctrl('p')) => finder.step(false),
+                    Input::Key(Key::Down | Key::Ctrl('n')) => finder.step(true),
+                    _ => finder.filter(query),
+                }
+            }
+        })?;
+        // Esc drops the finder
+        if let Some(finder) = self.finder.take() {
+            match finder.selected() {
+                Some(file) => self.edit(&file.clone(), false),
+                None => {
+                    self.set_status_message("No matching file");
+                    self.bell();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `:enew`, start an unnamed document, `:enew!` drops unsaved changes
+    fn edit_new(&mut self, force: bool) {
+        if self.can_abandon(force) {
+            self.set_document(Document::default());
+        }
+    }
+
+    // whether the document may be replaced, unsaved changes are only
+    // dropped with `force`
+    fn can_abandon(&mut self, force: bool) -> bool {
+        let hidden_dirty = self.hidden.as_ref().map_or(false, |hidden| hidden.document.is_dirty());
+        if (self.document.is_dirty() || hidden_dirty) && !force {
+            self.set_status_message("No write since last change (add ! to override)");
+            self.bell();
+            return false;
+        }
+        true
+    }
+
+    fn set_document(&mut self, document: Document) {
+        self.close_scratch();
+        if let Some(filename) = self.document.filename.clone() {
+            self.alternate = Some((filename, self.cursor_pos));
+        }
+        self.document = document;
+        self.cursor_pos = Pos::default();
+        self.offset = Pos::default();
+        self.last_selection = None;
+        self.highlighted_rows = None;
+        self.marks.clear();
+        for view in self.windows.views_mut() {
+            *view = View::default();
+        }
+    }
+
+    // `:e` / `:e!` without a file name, read the current file again from
+    // disk, the document is kept when the file is gone
+    fn reload(&mut self, force: bool) {
+        let filename = match self.document.filename.clone() {
+            Some(filename) => filename,
+            None => {
+                self.set_status_message("No file name");
+                self.bell();
+                return;
+            }
+        };
+        if !Path::new(&filename).is_file() {
+            self.set_status_message(&format!("ERR: Failed to open file: {}", filename));
+            self.bell();
+            return;
+        }
+        self.edit(&filename, force);
+    }
+
+    // `:make [args]`, run `makeprg` with `args` and go to the first
+    // location in its output
+    fn make(&mut self, args: &str) -> Result<(), std::io::Error> {
+        let cmd = format!("{} {}", self.config.makeprg, args);
+        let cmd = cmd.trim();
+        self.set_status_message(&format!(":!{}", cmd));
+        self.refresh_screen()?;
+        match Quickfix::make(cmd) {
+            Ok(quickfix) if quickfix.is_empty() => {
+                self.quickfix = quickfix;
+                self.set_status_message(&format!("No errors from {}", cmd));
+            }
+            Ok(quickfix) => {
+                self.quickfix = quickfix;
+                self.goto_quickfix();
+            }
+            Err(_) => {
+                self.set_status_message(&format!("ERR: Failed to run {}", cmd));
+                self.bell();
+            }
+        }
+        Ok(())
+    }
+
+    // `:grep text`, the lines with `text` in the files under the working
+    // directory (ignoring case as searches do) go in the quickfix list
+    fn grep(&mut self, text: &str) -> Result<(), std::io::Error> {
+        let regex = match self.search_regex(text) {
+            Some(regex) => regex,
+            None => {
+                self.set_status_message("Argument required");
+                self.bell();
+                return Ok(());
+            }
+        };
+        self.set_status_message(&format!(":grep {}", text));
+        self.refresh_screen()?;
+        self.quickfix = Quickfix::grep(&regex, &finder::files(".", FINDER_DEPTH));
+        if self.quickfix.is_empty() {
+            self.set_status_message(&format!("No match: {}", text));
+            self.bell();
+        } else {
+            self.goto_quickfix();
+        }
+        Ok(())
+    }
+
+    // `:cn` / `:cp`
+    fn quickfix_step(&mut self, forward: bool) {
+        if self.quickfix.step(forward) {
+            self.goto_quickfix();
+        } else if self.quickfix.is_empty() {
+            self.set_status_message("No errors");
+            self.bell();
+        } else {
+            self.set_status_message("No more items");
+            self.bell();
+        }
+    }
+
+    // `:cc`, open the file of the current quickfix entry at its location
+    fn goto_quickfix(&mut self) {
+        let (index, entry) = match self.quickfix.current() {
+            Some(current) => current,
+            None => {
+                self.set_status_message("No errors");
+                self.bell();
+                return;
+            }
+        };
+        let filename = entry.filename.clone();
+        let pos = Pos {
+            x: entry.col.saturating_sub(1),
+            y: entry.line.saturating_sub(1),
+        };
+        let message = format!("({} of {}) {}", index.saturating_add(1), self.quickfix.len(), entry.message);
+        if !self.is_current_file(&filename) {
+            self.edit(&filename, false);
+            if !self.is_current_file(&filename) {
+                return;
+            }
+        }
+        self.cursor_pos = self.clamp_pos(pos);
+        self.set_status_message(&message);
+    }
+
+    fn is_current_file(&self, filename: &str) -> bool {
+        match &self.document.filename {
+            Some(current) => match (fs::canonicalize(current), fs::canonicalize(filename)) {
+                (Ok(current), Ok(path)) => current == path,
+                _ => current == filename,
             },
-            Mode::Visual => {
-                print!("{}", cursor::SteadyBlock);
+            None => false,
+        }
+    }
+
+    // `Ctrl-]`, go to the definition of the word under the cursor found in
+    // the `tags` file next to the current file or in the working directory
+    fn jump_to_tag(&mut self) {
+        let Pos { x, y } = self.cursor_pos;
+        let name = match self.document.row(y).and_then(|row| row.word_at(x)) {
+            Some(name) => name,
+            None => {
+                self.set_status_message("No identifier under cursor");
+                self.bell();
+                return;
+            }
+        };
+        let dir = self
+            .document
+            .filename
+            .as_deref()
+            .and_then(|filename| Path::new(filename).parent())
+            .unwrap_or_else(|| Path::new(""));
+        let path = match [dir.join("tags"), Path::new("tags").to_path_buf()]
+            .into_iter()
+            .find(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => {
+                self.set_status_message("No tags file");
+                self.bell();
+                return;
+            }
+        };
+        let tag = match tags::find(&path, &name) {
+            Ok(Some(tag)) => tag,
+            Ok(None) => {
+                self.set_status_message(&format!("tag not found: {}", name));
+                self.bell();
+                return;
+            }
+            Err(_) => {
+                self.set_status_message(&format!("ERR: Failed to read {}", path.display()));
+                self.bell();
+                return;
+            }
+        };
+        let from = (self.document.filename.clone(), self.cursor_pos);
+        if !self.is_current_file(&tag.filename) {
+            self.edit(&tag.filename, false);
+            if !self.is_current_file(&tag.filename) {
+                return;
+            }
+        }
+        let pos = match &tag.address {
+            Address::Line(line) => Some(Pos { x: 0, y: line.saturating_sub(1) }),
+            Address::Pattern(regex) => self.document.find(regex, &Pos::default(), SearchDirection::Forward),
+        };
+        self.tag_stack.push(from);
+        match pos {
+            Some(pos) => {
+                self.cursor_pos = self.clamp_pos(pos);
+                self.set_status_message(&format!("tag: {}", name));
+            }
+            None => {
+                self.set_status_message(&format!("Couldn't find tag {} in {}", name, tag.filename));
+                self.bell();
+            }
+        }
+    }
+
+    // `Ctrl-T`, back to where the last `Ctrl-]` jumped from
+    fn pop_tag(&mut self) {
+        let (filename, pos) = match self.tag_stack.last() {
+            Some(from) => from.clone(),
+            None => {
+                self.set_status_message("at bottom of tag stack");
+                self.bell();
+                return;
+            }
+        };
+        if let Some(filename) = &filename {
+            if !self.is_current_file(filename) {
+                self.edit(filename, false);
+                if !self.is_current_file(filename) {
+                    return;
+                }
+            }
+        }
+        self.tag_stack.pop();
+        self.cursor_pos = self.clamp_pos(pos);
+    }
+
+    // `gf`, edit the file whose path is under the cursor, relative paths are
+    // taken from the directory of the current file
+    fn goto_file(&mut self) {
+        let Pos { x, y } = self.cursor_pos;
+        let path = match self.document.row(y).and_then(|row| row.path_at(x)) {
+            Some(path) => path,
+            None => {
+                self.set_status_message("No file name under cursor");
+                self.bell();
+                return;
+            }
+        };
+        let dir = self
+            .document
+            .filename
+            .as_deref()
+            .and_then(|filename| Path::new(filename).parent())
+            .unwrap_or_else(|| Path::new(""));
+        let file = dir.join(&path);
+        if !file.is_file() {
+            self.set_status_message(&format!("Can't find file \"{}\" in path", path));
+            self.bell();
+            return;
+        }
+        self.edit(&file.to_string_lossy(), false);
+    }
+
+    // with `force` (`:w!`) the file is written even if it was changed on
+    // disk since it was read, returns whether it was written
+    fn save(&mut self, force: bool) -> bool {
+        if self.document.is_scratch() {
+            self.set_status_message("Cannot write a scratch buffer");
+            self.bell();
+            return false;
+        }
+        if self.document.filename.is_none() {
+            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
+            if new_name.is_none() {
+                self.set_status_message("Save aborted");
+                return false;
+            }
+            self.document.filename = new_name;
+        }
+
+        if !force && self.document.changed_on_disk() {
+            self.set_status_message("WARNING: The file has been changed since reading it (use :w! to overwrite)");
+            self.bell();
+            return false;
+        }
+        if self.config.trimtrailing && self.document.is_modifiable() {
+            self.document.trim_trailing();
+            self.cursor_pos = self.clamp_pos(self.cursor_pos);
+        }
+        if self.document.save().is_ok() {
+            if self.document.is_lossy() {
+                self.set_status_message("File saved, invalid UTF-8 bytes were altered");
+            } else {
+                self.set_status_message("File saved successfully");
+            }
+            self.git_branch = git_branch();
+            true
+        } else {
+            self.set_status_message("Failed to save file");
+            false
+        }
+    }
+
+    fn change_mode(&mut self, mode: Mode) {
+        if (mode == Mode::Insert || mode == Mode::Replace) && !self.document.is_modifiable() {
+            self.set_status_message("Cannot make changes, 'modifiable' is off");
+            self.bell();
+            return;
+        }
+        let old_mode = self.mode;
+        if old_mode.is_visual() && !mode.is_visual() {
+            self.last_selection = Some(Selection {
+                anchor: self.visual_anchor,
+                cursor: self.cursor_pos,
+                mode: old_mode,
+            });
+        } else if !old_mode.is_visual() && mode.is_visual() {
+            self.visual_anchor = self.cursor_pos;
+        }
+        if mode == Mode::Insert && old_mode != Mode::Insert {
+            self.insert_start = self.cursor_pos;
+        }
+        self.mode = mode;
+        Terminal::cursor_style(self.cursor_style());
+        if mode == Mode::Normal && (old_mode == Mode::Insert || old_mode == Mode::Replace) {
+            self.normal_move_cursor('h');
+        }
+        self.replaced.clear();
+    }
+
+    // the cursor of the current mode, see `:set guicursor`
+    fn cursor_style(&self) -> CursorStyle {
+        let guicursor = &self.config.guicursor;
+        match self.mode {
+            Mode::Normal => guicursor.normal,
+            Mode::Insert => guicursor.insert,
+            Mode::Replace => guicursor.replace,
+            Mode::Visual | Mode::VisualLine => guicursor.visual,
+        }
+    }
+
+    // clamp a position that may be stale (e.g. the document changed since it
+    // was recorded) into the document, not allowing it to sit on \n
+    fn clamp_pos(&self, pos: Pos) -> Pos {
+        let y = cmp::min(pos.y, self.document.len().saturating_sub(1));
+        let x = if let Some(row) = self.document.row(y) {
+            cmp::min(pos.x, row.len().saturating_sub(1))
+        } else {
+            0
+        };
+        Pos { x, y }
+    }
+
+    // ========================================================
+    // |                                                      |
+    // |                     VISUAL MODE                      |
+    // |                                                      |
+    // ========================================================
+    fn visual_process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        self.document.start_change(self.cursor_pos);
+        match pressed_key {
+            Key::Char(c) => match c {
+                'h' | 'j' | 'k' | 'l' => self.normal_move_cursor(c),
+                '^' => self.move_cursor_thisline_first_char(),
+                'v' => self.toggle_visual(Mode::Visual),
+                'V' => self.toggle_visual(Mode::VisualLine),
+                // to the other end of the selection, to extend it from there
+                'o' => mem::swap(&mut self.cursor_pos, &mut self.visual_anchor),
+                'd' | 'x' => self.visual_delete(),
+                '>' | '<' => {
+                    let (start, end) = self.selection_bounds();
+                    self.change_mode(Mode::Normal);
+                    self.shift_rows(start.y, end.y, c == '>');
+                }
+                'g' => {
+                    if self.read_pending_key('g')? == Key::Char('q') {
+                        let (start, end) = self.selection_bounds();
+                        self.change_mode(Mode::Normal);
+                        self.format_rows(start.y, end.y);
+                    }
+                    self.pending_keys.clear();
+                }
+                _ => (),
             },
+            Key::Esc | Key::Ctrl('c') => self.change_mode(Mode::Normal),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // `v` in characterwise or `V` in linewise visual mode leaves it,
+    // otherwise we switch the kind of selection
+    fn toggle_visual(&mut self, mode: Mode) {
+        if self.mode == mode {
+            self.change_mode(Mode::Normal);
+        } else {
+            self.mode = mode;
+        }
+    }
+
+    // the selection as (start, end), both inclusive
+    fn selection_bounds(&self) -> (Pos, Pos) {
+        let anchor = self.visual_anchor;
+        let cursor = self.cursor_pos;
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        }
+    }
+
+    // the graphemes of row `y` covered by the selection, if any
+    fn selected_range(&self, y: usize, row: &Row) -> Option<Range<usize>> {
+        if !self.mode.is_visual() || self.other_window {
+            return None;
+        }
+        let (start, end) = self.selection_bounds();
+        if y < start.y || y > end.y {
+            return None;
+        }
+        if self.mode == Mode::VisualLine {
+            return Some(0..row.len().saturating_add(1));
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y {
+            end.x.saturating_add(1)
+        } else {
+            row.len().saturating_add(1) // the newline is selected as well
+        };
+        Some(from..to)
+    }
+
+    fn visual_delete(&mut self) {
+        let mode = self.mode;
+        let (start, end) = self.selection_bounds();
+        self.change_mode(Mode::Normal);
+        if mode == Mode::VisualLine {
+            self.yank_rows(start.y, end.y);
+            self.document.delete_rows(start.y, end.y);
+            self.cursor_pos = Pos { x: 0, y: start.y };
+            self.cursor_pos = self.clamp_pos(self.cursor_pos);
+            self.move_cursor_thisline_first_char();
+        } else {
+            let end = match self.document.row(end.y) {
+                // a selected newline joins the next row
+                Some(row) if end.x >= row.len() => Pos { x: 0, y: end.y.saturating_add(1) },
+                _ => Pos { x: end.x.saturating_add(1), y: end.y },
+            };
+            self.operate_on_range('d', start, end);
+        }
+    }
+
+    // `gv`, restore the last selection, clamped in case the document changed
+    fn reselect_visual(&mut self) {
+        if let Some(selection) = self.last_selection {
+            self.change_mode(selection.mode);
+            self.visual_anchor = self.clamp_pos(selection.anchor);
+            self.cursor_pos = self.clamp_pos(selection.cursor);
+        } else {
+            self.bell();
         }
     }
 
@@ -446,146 +1813,1368 @@ impl Editor {
     // |                     INSERT MODE                      |
     // |                                                      |
     // ========================================================
-    fn insert_process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+    fn insert_process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        if self.insert_repeat.is_some() {
+            match pressed_key {
+                Key::Char(c) => self.inserted.push(c),
+                Key::Backspace => {
+                    self.inserted.pop();
+                }
+                Key::Esc | Key::Ctrl('c') => self.repeat_insert()?,
+                _ => self.insert_repeat = None,
+            }
+        }
+        self._insert_process_keypress(pressed_key)
+    }
+
+    // typing the inserted text count - 1 more times when leaving insert mode
+    // after `3ihello`
+    fn repeat_insert(&mut self) -> Result<(), std::io::Error> {
+        let (count, rows) = match self.insert_repeat.take() {
+            Some(repeat) => repeat,
+            None => return Ok(()),
+        };
+        let text = mem::take(&mut self.inserted);
+        for _ in 1..count {
+            if rows {
+                self.open_row(true);
+            }
+            for c in text.chars() {
+                self._insert_process_keypress(Key::Char(c))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn _insert_process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        if pressed_key != Key::Ctrl('n') && pressed_key != Key::Ctrl('p') {
+            self.completion = None;
+        }
+        // with several cursors only typing and deleting in the row is done
+        // at each of them, anything else is done at the main cursor only
+        if !self.cursors.is_empty() {
+            match pressed_key {
+                Key::Char('\t') => {
+                    for _ in 0..self.config.tabstop {
+                        self.edit_at_cursors(Key::Char(' '));
+                    }
+                    return Ok(());
+                }
+                Key::Char(c) if c != '\n' => {
+                    self.edit_at_cursors(pressed_key);
+                    return Ok(());
+                }
+                Key::Backspace | Key::Delete => {
+                    self.edit_at_cursors(pressed_key);
+                    return Ok(());
+                }
+                _ => self.cursors.clear(),
+            }
+        }
         match pressed_key {
             Key::Char(c) => {
                 match c {
                     '\t' => {
-                        for _ in 0..TABSIZE {
+                        for _ in 0..self.config.tabstop {
                             self.document.insert(&self.cursor_pos, ' ');
                             self.move_cursor(Key::Right);
                         }
                     }
-                    _ => {
-                        self.document.insert(&self.cursor_pos, c);
-                        self.move_cursor(Key::Right);
-                    }
+                    _ => self.insert_char(c),
                 }
             },
             Key::Delete => self.document.delete(&self.cursor_pos),
             Key::Backspace => {
-                if self.cursor_pos.x > 0 || self.cursor_pos.y > 0 {
+                let Pos { x, y } = self.cursor_pos;
+                let allowed = if x > 0 {
+                    x > self.backspace_limit()
+                } else {
+                    y > 0 && self.can_join_row()
+                };
+                if !allowed {
+                    self.bell();
+                } else if self.config.autopair && self.in_empty_pair() {
+                    self.move_cursor(Key::Left);
+                    self.document.delete(&self.cursor_pos);
+                    self.document.delete(&self.cursor_pos);
+                } else {
                     self.move_cursor(Key::Left);
                     self.document.delete(&self.cursor_pos);
                 }
             },
-            Key::Up | Key::Down | Key::Left | Key::Right => self.move_cursor(pressed_key),
-            Key::Esc => self.change_mode(Mode::Normal),
+            Key::Ctrl('w') => {
+                let Pos { x, y } = self.cursor_pos;
+                let start = self.document.row(y).map_or(0, |row| row.delete_word_start(x));
+                self.delete_before_cursor(start);
+            }
+            // keeps the indentation, unless the cursor is already in it
+            Key::Ctrl('u') => {
+                let Pos { x, y } = self.cursor_pos;
+                let first = self.document.row(y).map_or(0, Row::index_first_char);
+                self.delete_before_cursor(if x > first { first } else { 0 });
+            }
+            Key::Up | Key::Down | Key::Left | Key::Right => {
+                self.move_cursor(pressed_key);
+                self.insert_start = self.cursor_pos;
+            }
+            Key::Ctrl('v') => self.insert_char_code()?,
+            Key::Ctrl('r') => self.insert_register()?,
+            Key::Ctrl('a') if self.config.emacskeys => self.cursor_pos.x = 0,
+            Key::Ctrl('e') if self.config.emacskeys => self.move_cursor_thisline_end(),
+            Key::Ctrl('o') => self.insert_normal_command()?,
+            Key::Ctrl('t') => self.shift_row(self.cursor_pos.y, true),
+            Key::Ctrl('d') => self.shift_row(self.cursor_pos.y, false),
+            Key::Ctrl('n') => self.complete(SearchDirection::Forward),
+            Key::Ctrl('p') => self.complete(SearchDirection::Backward),
+            Key::Esc | Key::Ctrl('c') => self.change_mode(Mode::Normal),
             _ => ()
         }
         Ok(())
     }
 
-    // ========================================================
-    // |                                                      |
-    // |                     NORMAL MODE                      |
-    // |                                                      |
-    // ========================================================
-    fn normal_process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        self._normal_process_keypress(pressed_key);
+    // `R`, typed characters overwrite the ones under the cursor, past the
+    // end of the row (or for Enter) they are inserted
+    fn replace_process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        match pressed_key {
+            Key::Char('\t') => {
+                for _ in 0..self.config.tabstop {
+                    self.replace_char(' ');
+                }
+            }
+            Key::Char(c) => self.replace_char(c),
+            Key::Backspace => {
+                if self.replaced.is_empty() {
+                    // only the replaced text is restored
+                    if self.cursor_pos.x > 0 {
+                        self.move_cursor(Key::Left);
+                    } else {
+                        self.bell();
+                    }
+                    return Ok(());
+                }
+                self.move_cursor(Key::Left);
+                if let Some(Some(original)) = self.replaced.pop() {
+                    self.document.delete(&self.cursor_pos);
+                    self.document.insert_str(&self.cursor_pos, &original);
+                } else {
+                    self.document.delete(&self.cursor_pos);
+                }
+            }
+            // moving away forgets what was replaced
+            key @ (Key::Up | Key::Down | Key::Left | Key::Right) => {
+                self.replaced.clear();
+                self.move_cursor(key);
+            }
+            Key::Esc | Key::Ctrl('c') => self.change_mode(Mode::Normal),
+            _ => (),
+        }
         Ok(())
     }
 
-    // wrapped function, for recursive use
-    // TODO: function getting too long, try to split it into multiple functions
-    fn _normal_process_keypress(&mut self, pressed_key: Key) {
-        match pressed_key {
-            Key::Char(c) => match c {
-                'i' => self.change_mode(Mode::Insert),
-                'a' => {
-                    self.move_cursor(Key::Right);
-                    self.change_mode(Mode::Insert);
-                }
-                'A' => {
-                    self.move_cursor_thisline_end();
-                    self._normal_process_keypress(Key::Char('i'));
-                }
-                'I' => {
-                    self.move_cursor_thisline_front();
-                    self._normal_process_keypress(Key::Char('i'));
-                }
-                'v' => self.change_mode(Mode::Visual),
-                'h' | 'j' | 'k' | 'l' => self.normal_move_cursor(c),
-                'x' => self.document.delete(&self.cursor_pos),
-                's' => {
-                    self._normal_process_keypress(Key::Char('x'));
-                    self._normal_process_keypress(Key::Char('i'));
-                }
-                ':' => self.parse_command(),
-                '/' => self.search(),
-                'o' => {
-                    if self.normal_insert_newline() {
-                        self.move_cursor_nextline_front();
-                        self.change_mode(Mode::Insert);
-                    }
-                }
-                'O' => {
-                    self.move_cursor(Key::Up);
-                    self._normal_process_keypress(Key::Char('o'));
-                }
-                '^' => self.move_cursor_thisline_first_char(),
-                '\n' => {
-                    self.move_cursor(Key::Down);
-                    self.move_cursor_thisline_first_char();
-                }
-                _ => (),
+    fn replace_char(&mut self, c: char) {
+        let Pos { x, y } = self.cursor_pos;
+        let original = match self.document.row(y).and_then(|row| row.grapheme_at(x)) {
+            Some(grapheme) if c != '\n' => Some(grapheme.to_owned()),
+            _ => None,
+        };
+        if original.is_some() {
+            self.document.delete(&self.cursor_pos);
+        }
+        self.document.insert(&self.cursor_pos, c);
+        self.move_cursor(Key::Right);
+        self.replaced.push(original);
+    }
+
+    // `Ctrl-A` / `Ctrl-X`, add (subtract) the count to the number at or
+    // after the cursor, which ends on its last character
+    fn add_to_number(&mut self, add: bool) {
+        let Pos { x, y } = self.cursor_pos;
+        let row = match self.document.row(y) {
+            Some(row) => row,
+            None => return,
+        };
+        let count = i64::try_from(cmp::max(self.count, 1)).unwrap_or(i64::MAX);
+        let amount = if add { count } else { count.saturating_neg() };
+        let (range, text) = match increment::increment(row.as_str(), row.byte_index(x), amount, self.config.nrformats) {
+            Some(change) => change,
+            None => {
+                self.bell();
+                return;
+            }
+        };
+        let start = Pos { x: row.grapheme_index(range.start), y };
+        let end = Pos { x: row.grapheme_index(range.end), y };
+        self.document.delete_range(&start, &end);
+        let after = self.document.insert_str(&start, &text);
+        self.cursor_pos = Pos { x: after.x.saturating_sub(1), y };
+    }
+
+    // `Ctrl-T` / `Ctrl-D` and `>` / `<`, indent or dedent row `y` to the next
+    // multiple of shiftwidth, the cursor stays on the same character
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    fn shift_row(&mut self, y: usize, right: bool) {
+        let step = self.config.shift_width();
+        let indent = match self.document.row(y) {
+            Some(row) => row.indent(),
+            None => return,
+        };
+        let new_indent = if right {
+            (indent / step + 1) * step
+        } else {
+            indent.saturating_sub(1) / step * step
+        };
+        self.document.set_indent(y, new_indent);
+        if self.cursor_pos.y == y {
+            self.cursor_pos.x = self.cursor_pos.x.saturating_add(new_indent).saturating_sub(indent);
+        }
+    }
+
+    // `Ctrl-O`, run one normal mode command and go back to insert mode,
+    // unless the command changed the mode itself (e.g. `v` or `i`). The
+    // cursor is not moved back like when leaving insert mode with `Esc`
+    fn insert_normal_command(&mut self) -> Result<(), std::io::Error> {
+        self.mode = Mode::Normal;
+        self.insert_normal = true;
+        let result = self
+            .refresh_screen()
+            .and_then(|_| self.read_key())
+            .and_then(|key| self.normal_process_keypress(key));
+        self.insert_normal = false;
+        if self.mode == Mode::Normal {
+            self.mode = Mode::Insert;
+        }
+        result
+    }
+
+    // text pasted in the terminal is inserted as is before the cursor,
+    // without autopair or auto-wrap. In normal mode too (as in Vim), it's
+    // then a change of its own and the cursor ends on its last character
+    fn insert_paste(&mut self, text: &str) {
+        if self.mode.is_visual() {
+            self.change_mode(Mode::Normal);
+        }
+        if self.mode == Mode::Normal {
+            self.document.start_change(self.cursor_pos);
+        }
+        // the pasted text is not typed again for the count of `3i`
+        self.insert_repeat = None;
+        self.cursor_pos = self.document.insert_str(&self.cursor_pos, text);
+        if self.mode == Mode::Normal {
+            self.cursor_pos = self.clamp_pos(Pos {
+                x: self.cursor_pos.x.saturating_sub(1),
+                ..self.cursor_pos
+            });
+        }
+    }
+
+    // `Ctrl-R {register}`, insert the text of a register before the cursor, a
+    // linewise register ends with a line break. `"` is the only register
+    fn insert_register(&mut self) -> Result<(), std::io::Error> {
+        self.pending_keys.push('^');
+        let key = self.read_pending_key('R')?;
+        self.pending_keys.clear();
+        match key {
+            Key::Null => (),
+            Key::Char('"') => {
+                let mut text = self.register.text.clone();
+                if self.register.linewise {
+                    text.push('\n');
+                }
+                self.cursor_pos = self.document.insert_str(&self.cursor_pos, &text);
+            }
+            _ => self.bell(),
+        }
+        Ok(())
+    }
+
+    // `Ctrl-V` followed by a character code (`u`/`U`/`x`/`o` and hex or
+    // octal digits, or decimal digits), or by any other key which is then
+    // inserted as is, e.g. a real tab or a control character
+    fn insert_char_code(&mut self) -> Result<(), std::io::Error> {
+        self.pending_keys.push_str("^V");
+        self.refresh_screen()?;
+        // not through read_pending_key, `Esc` is inserted too
+        let mut key = self.read_key()?;
+        let (radix, max_digits) = match key {
+            Key::Char('u') => (16, 4),
+            Key::Char('U') => (16, 8),
+            Key::Char('x' | 'X') => (16, 2),
+            Key::Char('o' | 'O') => (8, 3),
+            Key::Char(c) if c.is_ascii_digit() => (10, 3),
+            _ => {
+                self.pending_keys.clear();
+                if let Some(c) = literal_char(key) {
+                    self.document.insert(&self.cursor_pos, c);
+                    self.move_cursor(Key::Right);
+                }
+                return Ok(());
+            }
+        };
+        let mut digits = String::new();
+        if let Key::Char(c) = key {
+            if radix == 10 {
+                digits.push(c);
+            }
+            key = if digits.len() < max_digits {
+                self.read_pending_key(c)?
+            } else {
+                Key::Null
+            };
+        }
+        while let Key::Char(c) = key {
+            if !c.is_digit(radix) {
+                break;
+            }
+            digits.push(c);
+            key = if digits.len() < max_digits {
+                self.read_pending_key(c)?
+            } else {
+                Key::Null
+            };
+        }
+        self.pending_keys.clear();
+        // invalid code points (e.g. surrogates) are dropped
+        if let Some(c) = u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32) {
+            self.document.insert(&self.cursor_pos, c);
+            self.move_cursor(Key::Right);
+        }
+        if key != Key::Null {
+            self._insert_process_keypress(key)?;
+        }
+        Ok(())
+    }
+
+    // delete the row from column `start` to the cursor, at the start of a
+    // row it's joined with the previous one like Backspace does
+    fn delete_before_cursor(&mut self, start: usize) {
+        let Pos { x, y } = self.cursor_pos;
+        let start = cmp::max(start, self.backspace_limit());
+        if x > start {
+            self.document.delete_range(&Pos { x: start, y }, &self.cursor_pos);
+            self.cursor_pos.x = start;
+        } else if x == 0 && y > 0 && self.can_join_row() {
+            self.move_cursor(Key::Left);
+            self.document.delete(&self.cursor_pos);
+        } else {
+            self.bell();
+        }
+    }
+
+    // the first column of the cursor row that Backspace may delete, see
+    // `:set backspace`. Without `indent` the leading whitespace of the row
+    // is kept (there is no autoindent)
+    fn backspace_limit(&self) -> usize {
+        let Pos { x, y } = self.cursor_pos;
+        let backspace = self.config.backspace;
+        let mut limit = 0;
+        if !backspace.start && self.insert_start.y == y {
+            limit = self.insert_start.x;
+        }
+        if !backspace.indent {
+            let first = self.document.row(y).map_or(0, Row::index_first_char);
+            limit = if x <= first { x } else { cmp::max(limit, first) };
+        }
+        cmp::min(limit, x)
+    }
+
+    // whether Backspace at the start of the row may join it with the one
+    // above
+    fn can_join_row(&self) -> bool {
+        let backspace = self.config.backspace;
+        backspace.eol && (backspace.start || self.insert_start.y < self.cursor_pos.y)
+    }
+
+    // type a character (or delete one with Backspace / Delete, without
+    // joining rows) at every cursor, from the first one in the document on
+    // so that the cursors after an edit in the same row can be moved along
+    fn edit_at_cursors(&mut self, key: Key) {
+        let main = self.cursor_pos;
+        let mut cursors = self.cursors.clone();
+        cursors.push(main);
+        cursors.sort_by_key(|pos| (pos.y, pos.x));
+        cursors.dedup();
+        let mut moved = Vec::new();
+        let (mut row, mut added, mut removed) = (usize::MAX, 0_usize, 0_usize);
+        for pos in cursors {
+            if pos.y != row {
+                row = pos.y;
+                added = 0;
+                removed = 0;
+            }
+            let at = Pos {
+                x: pos.x.saturating_add(added).saturating_sub(removed),
+                y: pos.y,
+            };
+            let len = self.document.row(at.y).map_or(0, Row::len);
+            let new_pos = match key {
+                Key::Char(c) => {
+                    self.document.insert(&at, c);
+                    added = added.saturating_add(1);
+                    Pos { x: at.x.saturating_add(1), y: at.y }
+                }
+                Key::Backspace if at.x > 0 => {
+                    let at = Pos { x: at.x.saturating_sub(1), y: at.y };
+                    self.document.delete(&at);
+                    removed = removed.saturating_add(1);
+                    at
+                }
+                Key::Delete if at.x < len => {
+                    self.document.delete(&at);
+                    removed = removed.saturating_add(1);
+                    at
+                }
+                _ => at,
+            };
+            moved.push((pos == main, new_pos));
+        }
+        self.cursors.clear();
+        for (is_main, pos) in moved {
+            if is_main {
+                self.cursor_pos = pos;
+            } else {
+                self.cursors.push(pos);
+            }
+        }
+    }
+
+    // `Ctrl-N` in normal mode, add a cursor at the next occurrence of the
+    // word under the cursor, after the last added one
+    fn add_cursor(&mut self) {
+        let Pos { x, y } = self.cursor_pos;
+        let (word, start) = match self.document.row(y) {
+            Some(row) => match row.word_at(x) {
+                Some(word) => (word, row.word_start_before(x)),
+                None => {
+                    self.set_status_message("No word under cursor");
+                    self.bell();
+                    return;
+                }
+            },
+            None => return,
+        };
+        if self.cursors.is_empty() {
+            self.cursor_pos.x = start;
+        }
+        let last = self.cursors.last().copied().unwrap_or(self.cursor_pos);
+        let at = Pos { x: last.x.saturating_add(1), y: last.y };
+        let found = self
+            .document
+            .find_word(&word, &at, SearchDirection::Forward)
+            .or_else(|| self.document.find_word(&word, &Pos::default(), SearchDirection::Forward))
+            .filter(|pos| *pos != self.cursor_pos && !self.cursors.contains(pos));
+        match found {
+            Some(pos) => self.cursors.push(pos),
+            None => {
+                self.set_status_message(&format!("No more matches for {}", word));
+                self.bell();
+            }
+        }
+        self.highlighted_word = Regex::new(&regex::escape(&word)).ok();
+    }
+
+    // with `paste` the character is inserted as is
+    fn insert_char(&mut self, c: char) {
+        let paste = self.config.paste;
+        if self.config.autopair && !paste && self.autopair(c) {
+            return;
+        }
+        self.document.insert(&self.cursor_pos, c);
+        self.move_cursor(Key::Right);
+        if !c.is_whitespace() && !paste {
+            self.auto_wrap();
+        }
+    }
+
+    // with `autopair`, an opening char also inserts its closing char after the
+    // cursor, and a closing char typed right before the same one skips it.
+    // returns whether `c` was handled
+    fn autopair(&mut self, c: char) -> bool {
+        let Pos { x, y } = self.cursor_pos;
+        let next = self.document.row(y).and_then(|row| row.char_at(x));
+        if AUTOPAIRS.iter().any(|&(_, close)| close == c) && next == Some(c) {
+            self.move_cursor(Key::Right);
+            return true;
+        }
+        if let Some(&(open, close)) = AUTOPAIRS.iter().find(|&&(open, _)| open == c) {
+            self.document.insert(&self.cursor_pos, open);
+            self.move_cursor(Key::Right);
+            self.document.insert(&self.cursor_pos, close);
+            return true;
+        }
+        false
+    }
+
+    // the cursor is between an opening char and its closing char
+    fn in_empty_pair(&self) -> bool {
+        let Pos { x, y } = self.cursor_pos;
+        if x == 0 {
+            return false;
+        }
+        self.document.row(y).map_or(false, |row| {
+            let prev = row.char_at(x.saturating_sub(1));
+            let next = row.char_at(x);
+            AUTOPAIRS
+                .iter()
+                .any(|&(open, close)| prev == Some(open) && next == Some(close))
+        })
+    }
+
+    // `Ctrl-N`/`Ctrl-P`, replace the word before the cursor with the next or
+    // previous word of the buffer starting with it, cycling back to the prefix
+    fn complete(&mut self, direction: SearchDirection) {
+        if self.completion.is_none() {
+            let Pos { x, y } = self.cursor_pos;
+            let start = Pos {
+                x: self.document.row(y).map_or(x, |row| row.word_start_before(x)),
+                y,
+            };
+            let prefix = self.document.row(y).map_or(String::new(), |row| {
+                row.as_str().graphemes(true).skip(start.x).take(x.saturating_sub(start.x)).collect()
+            });
+            let candidates = self.document.words_with_prefix(&prefix, y.saturating_add(1));
+            if prefix.is_empty() || candidates.is_empty() {
+                self.set_status_message("No match");
+                self.bell();
+                return;
+            }
+            self.completion = Some(Completion { start, prefix, candidates, index: None });
+        }
+        if let Some(completion) = self.completion.as_mut() {
+            let count = completion.candidates.len();
+            // None (the prefix) sits between the last and the first candidate
+            completion.index = match (completion.index, direction) {
+                (None, SearchDirection::Forward) => Some(0),
+                (None, SearchDirection::Backward) => count.checked_sub(1),
+                (Some(i), SearchDirection::Forward) => Some(i.saturating_add(1)).filter(|&i| i < count),
+                (Some(i), SearchDirection::Backward) => i.checked_sub(1),
+            };
+            let text = completion
+                .index
+                .and_then(|i| completion.candidates.get(i))
+                .unwrap_or(&completion.prefix)
+                .clone();
+            let message = match completion.index {
+                Some(i) => format!("match {} of {}", i.saturating_add(1), count),
+                None => "Back at original".to_owned(),
+            };
+            let start = completion.start;
+            self.document.delete_range(&start, &self.cursor_pos);
+            self.cursor_pos = self.document.insert_str(&start, &text);
+            self.set_status_message(&message);
+        }
+    }
+
+    // the column typed text wraps after: `textwidth`, or `wrapmargin`
+    // columns before the right edge of the window, 0 is no wrapping
+    fn wrap_width(&self) -> usize {
+        if self.config.textwidth > 0 {
+            self.config.textwidth
+        } else if self.config.wrapmargin > 0 {
+            self.text_width().saturating_sub(self.config.wrapmargin)
+        } else {
+            0
+        }
+    }
+
+    // with `textwidth` or `wrapmargin` set, typing past the wrap width moves
+    // the last word(s) to a new line, the blanks at the break are dropped
+    fn auto_wrap(&mut self) {
+        let textwidth = self.wrap_width();
+        let Pos { x, y } = self.cursor_pos;
+        let range = match self.document.row(y) {
+            Some(row) if textwidth > 0 && row.width() > textwidth => row.wrap_range(textwidth),
+            _ => None,
+        };
+        if let Some(range) = range {
+            let start = Pos { x: range.start, y };
+            self.document.delete_range(&start, &Pos { x: range.end, y });
+            self.document.insert(&start, '\n');
+            if x >= range.end {
+                self.cursor_pos = Pos {
+                    x: x.saturating_sub(range.end),
+                    y: y.saturating_add(1),
+                };
+            }
+        }
+    }
+
+    // ========================================================
+    // |                                                      |
+    // |                     NORMAL MODE                      |
+    // |                                                      |
+    // ========================================================
+    fn normal_process_keypress(&mut self, mut pressed_key: Key) -> Result<(), std::io::Error> {
+        // the matches of `*` stay colored, but are not updated anymore
+        self.highlighted_word = None;
+        self.count = 0;
+        // a count is a number typed before the command, a leading '0' is not
+        while let Key::Char(c) = pressed_key {
+            match c.to_digit(10) {
+                Some(digit) if digit > 0 || self.count > 0 => {
+                    self.count = self.count.saturating_mul(10).saturating_add(digit as usize);
+                    pressed_key = self.read_pending_key(c)?;
+                }
+                _ => break,
+            }
+        }
+        // the other cursors are kept to insert at all of them, anything else
+        // (e.g. Esc) drops them
+        if !self.cursors.is_empty() {
+            match pressed_key {
+                Key::Ctrl('n') | Key::Char('i') => (),
+                Key::Char('a') => {
+                    for pos in &mut self.cursors {
+                        let len = self.document.row(pos.y).map_or(0, Row::len);
+                        pos.x = cmp::min(pos.x.saturating_add(1), len);
+                    }
+                }
+                _ => self.cursors.clear(),
+            }
+        }
+        self.document.start_change(self.cursor_pos);
+        self.insert_repeat = match pressed_key {
+            Key::Char(c @ ('i' | 'a' | 'A' | 'I' | 'o' | 'O')) if self.count > 1 => Some((self.count, c == 'o' || c == 'O')),
+            _ => None,
+        };
+        self.inserted.clear();
+        let result = if REPEATABLE_KEYS.contains(&pressed_key) {
+            let mut result = Ok(());
+            for _ in 0..cmp::max(self.count, 1) {
+                result = self._normal_process_keypress(pressed_key);
+                if result.is_err() {
+                    break;
+                }
+            }
+            result
+        } else {
+            self._normal_process_keypress(pressed_key)
+        };
+        self.pending_keys.clear();
+        result
+    }
+
+    // wrapped function, for recursive use
+    // TODO: function getting too long, try to split it into multiple functions
+    fn _normal_process_keypress(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        match pressed_key {
+            Key::Char(c) => match c {
+                'q' => {
+                    if !self.close_scratch() {
+                        self.bell();
+                    }
+                }
+                ' ' => self.normal_step(true, self.config.whichwrap.space),
+                'i' => self.change_mode(Mode::Insert),
+                'R' => self.change_mode(Mode::Replace),
+                'a' => {
+                    self.move_cursor(Key::Right);
+                    self.change_mode(Mode::Insert);
+                }
+                'A' => {
+                    self.move_cursor_thisline_end();
+                    self._normal_process_keypress(Key::Char('i'))?;
+                }
+                'I' => {
+                    self.move_cursor_thisline_front();
+                    self._normal_process_keypress(Key::Char('i'))?;
+                }
+                'v' => self.change_mode(Mode::Visual),
+                'V' => self.change_mode(Mode::VisualLine),
+                'g' => {
+                    let next_key = self.read_pending_key('g')?;
+                    self.normal_process_g_prefix(next_key)?;
+                }
+                'z' => {
+                    let next_key = self.read_pending_key('z')?;
+                    self.normal_process_z_prefix(next_key);
+                }
+                'd' | 'c' | 'y' | '>' | '<' => {
+                    let next_key = self.read_pending_key(c)?;
+                    self.normal_process_operator(c, next_key)?;
+                }
+                'm' => match self.read_pending_key('m')? {
+                    Key::Char(c @ 'a'..='z') => {
+                        self.marks.insert(c, self.cursor_pos);
+                    }
+                    Key::Null => (),
+                    _ => self.bell(),
+                },
+                '\'' | '`' => match self.read_pending_key(c)? {
+                    Key::Char(mark @ 'a'..='z') => self.goto_mark(mark, c == '`'),
+                    Key::Null => (),
+                    _ => self.bell(),
+                },
+                'J' => self.join_rows(true),
+                '&' => self.repeat_substitute(false)?,
+                'p' => self.paste(true),
+                'P' => self.paste(false),
+                ']' | '[' => match self.read_pending_key(c)? {
+                    Key::Char('p') => self.paste_indented(c == ']'),
+                    Key::Char('P') => self.paste_indented(false),
+                    Key::Null => (),
+                    _ => self.bell(),
+                },
+                'Z' => match self.read_pending_key('Z')? {
+                    Key::Char('Z') => self.exit(),
+                    Key::Char('Q') => self.quit(true),
+                    _ => (),
+                },
+                'h' | 'j' | 'k' | 'l' => {
+                    let old_pos = self.cursor_pos;
+                    self.normal_move_cursor(c);
+                    if self.cursor_pos == old_pos {
+                        self.bell();
+                    }
+                }
+                'x' => self.document.delete(&self.cursor_pos),
+                'u' => self.undo(),
+                's' => {
+                    self._normal_process_keypress(Key::Char('x'))?;
+                    self._normal_process_keypress(Key::Char('i'))?;
+                }
+                ':' => self.parse_command()?,
+                '/' => self.search(),
+                'o' => self.open_row(true),
+                'O' => self.open_row(false),
+                '^' => self.move_cursor_thisline_first_char(),
+                '0' => self.cursor_pos.x = 0,
+                // from insert mode (`Ctrl-O $`) the cursor can stay past the end
+                '$' => {
+                    self.move_cursor_thisline_end();
+                    if !self.insert_normal {
+                        self.fix_if_cursor_at_newline();
+                    }
+                }
+                'G' => self.goto_row(self.counted_row(self.document.len().saturating_sub(1))),
+                '%' if self.count > 0 => self.goto_percent(self.count),
+                '%' => match self.document.matching_pair(&self.cursor_pos, &self.config.matchpairs) {
+                    Some(pos) => self.cursor_pos = pos,
+                    None => self.bell(),
+                },
+                '{' => self.move_paragraph(SearchDirection::Backward),
+                '}' => self.move_paragraph(SearchDirection::Forward),
+                '*' => self.search_word_under_cursor(SearchDirection::Forward),
+                '#' => self.search_word_under_cursor(SearchDirection::Backward),
+                '(' => self.move_sentence(SearchDirection::Backward),
+                ')' => self.move_sentence(SearchDirection::Forward),
+                '\n' => {
+                    self.move_cursor(Key::Down);
+                    self.move_cursor_thisline_first_char();
+                }
+                _ => (),
+            }
+            // never quits, only cancels what is pending (see read_pending_key)
+            Key::Ctrl('c') => {
+                self.set_status_message("Type :q! and <Enter> to abandon changes and exit Rum");
+            }
+            Key::Ctrl('r') => self.redo(),
+            Key::Ctrl('n') => self.add_cursor(),
+            Key::Ctrl('w') => {
+                self.pending_keys.push('^');
+                let key = self.read_pending_key('W')?;
+                self.window_command(key);
+            }
+            // termion reads `Ctrl-]` (0x1d) as Ctrl-5
+            Key::Ctrl('5') => self.jump_to_tag(),
+            Key::Ctrl('t') => self.pop_tag(),
+            Key::Ctrl('a') => self.add_to_number(true),
+            Key::Ctrl('x') => self.add_to_number(false),
+            // termion reads `Ctrl-6` (0x1e) as Ctrl-6, `Ctrl-^` sends the same
+            Key::Ctrl('6') => self.edit_alternate(false),
+            Key::Ctrl('g') => self.show_file_info(),
+            // everything is drawn again (and highlighted again) on the next
+            // refresh, e.g. after other programs wrote to the terminal
+            Key::Ctrl('l') => {
+                Terminal::clear_screen();
+                self.document.unhighlight_rows(0);
+            }
+            Key::Ctrl('e') => self.scroll_lines(SearchDirection::Forward),
+            Key::Ctrl('y') => self.scroll_lines(SearchDirection::Backward),
+            Key::Backspace => self.normal_step(false, self.config.whichwrap.backspace),
+            Key::Left => self.normal_step(false, self.config.whichwrap.left),
+            Key::Right => self.normal_step(true, self.config.whichwrap.right),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // `g-` / `g+`, count states back / forward in time through the undo tree
+    fn step_in_time(&mut self, forward: bool) {
+        for _ in 0..cmp::max(self.count, 1) {
+            if let Some(pos) = self.document.step_in_time(forward, self.cursor_pos) {
+                self.cursor_pos = self.clamp_pos(pos);
+            } else {
+                self.set_status_message(if forward { "Already at newest change" } else { "Already at oldest change" });
+                self.bell();
+                return;
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        for _ in 0..cmp::max(self.count, 1) {
+            if let Some(pos) = self.document.undo(self.cursor_pos) {
+                self.cursor_pos = self.clamp_pos(pos);
+            } else {
+                self.set_status_message("Already at oldest change");
+                self.bell();
+                return;
+            }
+        }
+    }
+
+    fn redo(&mut self) {
+        for _ in 0..cmp::max(self.count, 1) {
+            if let Some(pos) = self.document.redo(self.cursor_pos) {
+                self.cursor_pos = self.clamp_pos(pos);
+            } else {
+                self.set_status_message("Already at newest change");
+                self.bell();
+                return;
+            }
+        }
+    }
+
+    // read the next key of a multi-key command, `typed` (the last key read)
+    // is shown with the rest of the pending keys while waiting.
+    // `Esc` or `Ctrl-C` cancel the command and are returned as `Key::Null`
+    // which no command uses, as is no key within `timeoutlen` with `timeout`
+    fn read_pending_key(&mut self, typed: char) -> Result<Key, std::io::Error> {
+        self.pending_keys.push(typed);
+        self.refresh_screen()?;
+        let key = if self.config.timeout {
+            let timeout = Duration::from_millis(self.config.timeoutlen as u64);
+            match self.terminal.read_input_timeout(timeout)? {
+                Some(input) => self.input_key(input),
+                None => Key::Null,
+            }
+        } else {
+            self.read_key()?
+        };
+        match key {
+            Key::Esc | Key::Ctrl('c') => Ok(Key::Null),
+            key => Ok(key),
+        }
+    }
+
+    fn normal_process_g_prefix(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        match pressed_key {
+            Key::Null => (),
+            Key::Char('g') => self.goto_row(self.counted_row(0)),
+            Key::Char('v') => self.reselect_visual(),
+            Key::Char('a') => self.show_char_code(),
+            Key::Char('J') => self.join_rows(false),
+            Key::Char('f') => self.goto_file(),
+            Key::Char('d') => self.goto_definition(),
+            Key::Char('&') => self.repeat_substitute(true)?,
+            Key::Char('-') => self.step_in_time(false),
+            Key::Char('+') => self.step_in_time(true),
+            // `gU`, `gu` and `g~` are operators like `d`, written as their
+            // second key
+            Key::Char(c @ ('U' | 'u' | '~')) => {
+                let next_key = self.read_pending_key(c)?;
+                self.normal_process_operator(c, next_key)?;
+            }
+            Key::Char('q') => {
+                let y = self.cursor_pos.y;
+                let range = match self.read_pending_key('q')? {
+                    Key::Null => return Ok(()),
+                    Key::Char('q') => Some((y, y)),
+                    Key::Char('j') => Some((y, y.saturating_add(1))),
+                    Key::Char('k') => Some((y.saturating_sub(1), y)),
+                    Key::Char(c @ ('a' | 'i')) => match self.read_pending_key(c)? {
+                        Key::Char('p') => self.document.paragraph(y),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some((start, end)) = range {
+                    self.format_rows(start, end);
+                } else {
+                    self.bell();
+                }
+            }
+            _ => self.bell(),
+        }
+        Ok(())
+    }
+
+    // `d`, `c`, `y`, `>`, `<` (or `gU`, `gu`, `g~` as `U`, `u`, `~`) followed
+    // by itself (count rows from the cursor's), by `G` / `gg` (the rows up to
+    // the end / start, or row count), by `}` / `{` (the rows up to the
+    // count-th paragraph boundary), by `$` (to the end of the row, count - 1
+    // rows down) or by a text object
+    fn normal_process_operator(&mut self, operator: char, pressed_key: Key) -> Result<(), std::io::Error> {
+        let y = self.cursor_pos.y;
+        let last = self.document.len().saturating_sub(1);
+        let rows = match pressed_key {
+            Key::Char(c) if c == operator => {
+                let end = y.saturating_add(cmp::max(self.count, 1)).saturating_sub(1);
+                Some((y, cmp::min(end, last))).filter(|_| y < self.document.len())
+            }
+            Key::Char('G') => {
+                let target = self.counted_row(last);
+                Some((cmp::min(y, target), cmp::max(y, target))).filter(|_| y < self.document.len())
+            }
+            Key::Char('g') => match self.read_pending_key('g')? {
+                Key::Char('g') => {
+                    let target = self.counted_row(0);
+                    Some((cmp::min(y, target), cmp::max(y, target))).filter(|_| y < self.document.len())
+                }
+                // `gUgU`, like `gUU`
+                Key::Char(c) if c == operator => {
+                    let end = y.saturating_add(cmp::max(self.count, 1)).saturating_sub(1);
+                    Some((y, cmp::min(end, last))).filter(|_| y < self.document.len())
+                }
+                Key::Null => return Ok(()),
+                _ => None,
+            },
+            Key::Char('$') => {
+                let end_y = cmp::min(y.saturating_add(cmp::max(self.count, 1)).saturating_sub(1), last);
+                let end = Pos {
+                    x: self.document.row(end_y).map_or(0, Row::len),
+                    y: end_y,
+                };
+                self.operate_on_range(operator, self.cursor_pos, end);
+                return Ok(());
+            }
+            Key::Char('}') => Some(self.paragraph_rows(SearchDirection::Forward)).filter(|_| y < self.document.len()),
+            Key::Char('{') => Some(self.paragraph_rows(SearchDirection::Backward)).filter(|_| y < self.document.len()),
+            Key::Char(c @ ('a' | 'i')) => match self.read_pending_key(c)? {
+                Key::Char('p') => self.document.paragraph_object(y, c == 'a'),
+                Key::Char('w') => {
+                    let x = self.cursor_pos.x;
+                    match self.document.row(y).and_then(|row| row.word_object(x, c == 'a')) {
+                        Some(range) => {
+                            let start = Pos { x: range.start, y };
+                            self.operate_on_range(operator, start, Pos { x: range.end, y });
+                        }
+                        None => self.bell(),
+                    }
+                    return Ok(());
+                }
+                Key::Null => return Ok(()),
+                _ => None,
+            },
+            Key::Null => return Ok(()),
+            _ => None,
+        };
+        if let Some((start, end)) = rows {
+            self.operate_on_rows(operator, start, end);
+        } else {
+            self.bell();
+        }
+        Ok(())
+    }
+
+    fn yank_rows(&mut self, start: usize, end: usize) {
+        let end_x = self.document.row(end).map_or(0, Row::len);
+        self.register = Register {
+            text: self.document.text(&Pos { x: 0, y: start }, &Pos { x: end_x, y: end }),
+            linewise: true,
+        };
+    }
+
+    fn operate_on_rows(&mut self, operator: char, start: usize, end: usize) {
+        if let '>' | '<' = operator {
+            self.shift_rows(start, end, operator == '>');
+            return;
+        }
+        if let 'U' | 'u' | '~' = operator {
+            let end_x = self.document.row(end).map_or(0, Row::len);
+            self.change_case(operator, Pos { x: 0, y: start }, Pos { x: end_x, y: end });
+            return;
+        }
+        self.yank_rows(start, end);
+        match operator {
+            'd' => {
+                self.document.delete_rows(start, end);
+                // deleting every row leaves a single empty one
+                if self.document.is_empty() {
+                    self.document.insert_rows(0, "");
+                }
+                self.cursor_pos = self.clamp_pos(Pos { x: 0, y: start });
+                self.move_cursor_thisline_first_char();
+            }
+            'c' => {
+                self.document.delete_rows(start, end);
+                self.document.insert_rows(start, "");
+                self.cursor_pos = Pos { x: 0, y: start };
+                self.change_mode(Mode::Insert);
+            }
+            _ => {
+                self.cursor_pos = self.clamp_pos(Pos { x: self.cursor_pos.x, y: start });
+            }
+        }
+    }
+
+    // an operator on the text from `start` up to (excluding) `end`, e.g. on
+    // a word object
+    fn operate_on_range(&mut self, operator: char, start: Pos, end: Pos) {
+        if let '>' | '<' = operator {
+            self.shift_rows(start.y, end.y, operator == '>');
+            return;
+        }
+        if let 'U' | 'u' | '~' = operator {
+            self.change_case(operator, start, end);
+            return;
+        }
+        self.register = Register {
+            text: self.document.text(&start, &end),
+            linewise: false,
+        };
+        match operator {
+            'd' => {
+                self.document.delete_range(&start, &end);
+                self.cursor_pos = self.clamp_pos(start);
+            }
+            'c' => {
+                self.document.delete_range(&start, &end);
+                self.cursor_pos = start;
+                self.change_mode(Mode::Insert);
+            }
+            _ => self.cursor_pos = self.clamp_pos(start),
+        }
+    }
+
+    // `gU` / `gu` / `g~` (`operator` being their second key), upper / lower
+    // case or toggle the case of the text from `start` up to (excluding)
+    // `end`, the cursor goes to `start`
+    fn change_case(&mut self, operator: char, start: Pos, end: Pos) {
+        let text = self.document.text(&start, &end);
+        let changed: String = text
+            .chars()
+            .map(|c| match operator {
+                'U' => c.to_uppercase().collect(),
+                'u' => c.to_lowercase().collect(),
+                _ if c.is_lowercase() => c.to_uppercase().collect(),
+                _ => c.to_lowercase().collect::<String>(),
+            })
+            .collect();
+        if changed != text {
+            self.document.delete_range(&start, &end);
+            self.document.insert_str(&start, &changed);
+        }
+        self.cursor_pos = self.clamp_pos(start);
+    }
+
+    // `>` / `<` on rows, empty rows are left alone
+    fn shift_rows(&mut self, start: usize, end: usize, right: bool) {
+        for y in start..=end {
+            if self.document.row(y).map_or(false, |row| !row.is_empty()) {
+                self.shift_row(y, right);
+            }
+        }
+        self.cursor_pos.y = start;
+        self.move_cursor_thisline_first_char();
+    }
+
+    // `p` puts the register after the cursor (or below its row for linewise
+    // text), `P` before it
+    fn paste(&mut self, after: bool) {
+        if self.register.text.is_empty() && !self.register.linewise {
+            self.bell();
+            return;
+        }
+        let count = cmp::max(self.count, 1);
+        let Pos { x, y } = self.cursor_pos;
+        if self.register.linewise {
+            let text = vec![&self.register.text[..]; count].join("\n");
+            self.put_rows(&text, after);
+        } else {
+            let text = self.register.text.repeat(count);
+            let empty = self.document.row(y).map_or(true, Row::is_empty);
+            let at = Pos { x: if after && !empty { x.saturating_add(1) } else { x }, y };
+            let end = self.document.insert_str(&at, &text);
+            self.cursor_pos = self.clamp_pos(Pos { x: end.x.saturating_sub(1), y: end.y });
+        }
+    }
+
+    // `]p` / `[p`, like `p` / `P` but linewise text is reindented so that
+    // its first line gets the indent of the cursor row (of the nearest
+    // row above with text if the cursor row is blank)
+    fn paste_indented(&mut self, after: bool) {
+        if !self.register.linewise {
+            self.paste(after);
+            return;
+        }
+        let y = self.cursor_pos.y;
+        let indent = (0..=y)
+            .rev()
+            .chain(y.saturating_add(1)..self.document.len())
+            .filter_map(|y| self.document.row(y))
+            .find(|row| row.index_first_char() < row.len())
+            .map_or(0, Row::indent);
+        let lines: Vec<&str> = self.register.text.split('\n').collect();
+        let indent_of = |line: &str| line.len().saturating_sub(line.trim_start_matches(' ').len());
+        let base = lines
+            .iter()
+            .find(|line| !line.trim().is_empty())
+            .map_or(0, |line| indent_of(line));
+        let reindented: Vec<String> = lines
+            .iter()
+            .map(|line| {
+                if line.trim().is_empty() {
+                    return (*line).to_owned();
+                }
+                let new_indent = indent_of(line).saturating_add(indent).saturating_sub(base);
+                format!("{}{}", " ".repeat(new_indent), line.trim_start_matches(' '))
+            })
+            .collect();
+        let text = vec![reindented.join("\n"); cmp::max(self.count, 1)].join("\n");
+        self.put_rows(&text, after);
+    }
+
+    // `:registers`, the type (`l`inewise or `c`haracterwise) and the text of
+    // each register that is set, there is only the unnamed one so far
+    fn show_registers(&mut self) {
+        let mut lines = vec!["Type Name Content".to_owned()];
+        if !self.register.text.is_empty() || self.register.linewise {
+            let kind = if self.register.linewise { 'l' } else { 'c' };
+            let mut text = self.register.text.replace('\n', "^J");
+            if self.register.linewise {
+                text.push_str("^J");
+            }
+            lines.push(format!("  {}  \"\"   {}", kind, text));
+        }
+        self.show_list(&lines);
+    }
+
+    // `:marks`, the position of each mark and the text of its row, the row
+    // may be gone since the mark was set
+    fn show_marks(&mut self) {
+        // the marks are in the document under the scratch buffer
+        self.close_scratch();
+        let mut marks: Vec<(char, Pos)> = self.marks.iter().map(|(c, pos)| (*c, *pos)).collect();
+        marks.sort_unstable_by_key(|(c, _)| *c);
+        let mut lines = vec!["mark line  col text".to_owned()];
+        for (c, pos) in marks {
+            let text = match self.document.row(pos.y) {
+                Some(row) => row.as_str().trim().to_owned(),
+                None => "(invalid)".to_owned(),
+            };
+            lines.push(format!(" {} {:>6} {:>4} {}", c, pos.y.saturating_add(1), pos.x, text));
+        }
+        self.show_list(&lines);
+    }
+
+    // `:put` puts the register as whole rows below the cursor row (`:put!`
+    // above), `:put =text` puts `text` instead, quotes around it are dropped
+    fn put_command(&mut self, args: &str, after: bool) {
+        let text = match args.trim().strip_prefix('=') {
+            Some(text) => {
+                let text = text.trim();
+                let unquoted = ['"', '\'']
+                    .iter()
+                    .find_map(|&quote| text.strip_prefix(quote).and_then(|text| text.strip_suffix(quote)));
+                unquoted.unwrap_or(text).to_owned()
+            }
+            None if self.register.text.is_empty() && !self.register.linewise => {
+                self.set_status_message("Nothing in register \"");
+                self.bell();
+                return;
+            }
+            None => self.register.text.clone(),
+        };
+        self.put_rows(&text, after);
+    }
+
+    // put whole rows below (or above) the cursor row and move to the first
+    // of them
+    fn put_rows(&mut self, text: &str, after: bool) {
+        let y = self.cursor_pos.y;
+        let y = if after && !self.document.is_empty() { y.saturating_add(1) } else { y };
+        self.document.insert_rows(y, text);
+        self.cursor_pos = Pos { x: 0, y };
+        self.move_cursor_thisline_first_char();
+    }
+
+    // `J` joins count rows (at least two), the leading whitespace of the
+    // joined rows is replaced by a space (two after a sentence with
+    // `joinspaces`, none after whitespace or before a ')'), `gJ` joins them
+    // as they are
+    fn join_rows(&mut self, with_spaces: bool) {
+        let y = self.cursor_pos.y;
+        if y.saturating_add(1) >= self.document.len() {
+            self.bell();
+            return;
+        }
+        let joins = cmp::max(self.count, 2).saturating_sub(1);
+        for _ in 0..joins {
+            let (text, next) = match (self.document.row(y), self.document.row(y.saturating_add(1))) {
+                (Some(row), Some(next)) => (row.as_str().to_owned(), next.as_str().to_owned()),
+                _ => break,
+            };
+            let x = self.document.row(y).map_or(0, Row::len);
+            let at = Pos { x, y };
+            if !with_spaces {
+                self.document.delete(&at);
+                self.cursor_pos = at;
+                continue;
+            }
+            let indent = self.document.row(y.saturating_add(1)).map_or(0, Row::index_first_char);
+            self.document.delete_range(&at, &Pos { x: indent, y: y.saturating_add(1) });
+            let next = next.trim_start();
+            let spaces = if text.is_empty()
+                || text.ends_with(char::is_whitespace)
+                || next.is_empty()
+                || next.starts_with(')')
+            {
+                ""
+            } else if self.config.joinspaces && text.ends_with(['.', '!', '?'].as_ref()) {
+                "  "
+            } else {
+                " "
+            };
+            self.document.insert_str(&at, spaces);
+            self.cursor_pos = at;
+        }
+        self.cursor_pos = self.clamp_pos(self.cursor_pos);
+    }
+
+    // `Ctrl-G`, like the status bar but with the full path of the file
+    fn show_file_info(&mut self) {
+        let name = match &self.document.filename {
+            Some(filename) => fs::canonicalize(filename)
+                .map_or_else(|_| filename.clone(), |path| path.to_string_lossy().into_owned()),
+            None => "[No Name]".to_owned(),
+        };
+        let modified = if self.document.is_dirty() { " [Modified]" } else { "" };
+        let len = self.document.len();
+        let info = if len == 0 {
+            format!("\"{}\"{} --No lines in buffer--", name, modified)
+        } else {
+            #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+            let percent = cmp::min(self.cursor_pos.y.saturating_add(1), len) * 100 / len;
+            format!("\"{}\"{} {} lines --{}%--", name, modified, len, percent)
+        };
+        self.set_status_message(&info);
+    }
+
+    // `ga`, the code points of the grapheme under the cursor
+    fn show_char_code(&mut self) {
+        let Pos { x, y } = self.cursor_pos;
+        let grapheme = match self.document.row(y).and_then(|row| row.grapheme_at(x)) {
+            Some(grapheme) => grapheme.to_owned(),
+            None => {
+                self.set_status_message("NUL");
+                return;
             }
-            Key::Backspace => {
-                if self.cursor_pos.x == 0 {  // skip the newline
-                    self.move_cursor(Key::Left);
-                    self.move_cursor(Key::Left);
+        };
+        let codes: Vec<String> = grapheme
+            .chars()
+            .map(|c| {
+                let shown = if c.is_control() {
+                    c.escape_default().to_string()
                 } else {
-                    self.move_cursor(Key::Left);
-                }
-           }
-            _ => (),
+                    c.to_string()
+                };
+                format!("<{}> {}, Hex {:04x}, Oct {:o}", shown, c as u32, c as u32, c as u32)
+            })
+            .collect();
+        self.set_status_message(&codes.join(" + "));
+    }
+
+    fn normal_process_z_prefix(&mut self, pressed_key: Key) {
+        let y = self.cursor_pos.y;
+        let done = match pressed_key {
+            Key::Null => true,
+            Key::Char('g') => {
+                self.spell_add_word();
+                true
+            }
+            Key::Char('c') => self.document.close_fold(y),
+            Key::Char('o') => self.document.open_fold(y),
+            Key::Char('a') => self.document.open_fold(y) || self.document.close_fold(y),
+            Key::Char('R') => {
+                self.document.open_all_folds();
+                true
+            }
+            Key::Char('M') => {
+                self.document.close_all_folds();
+                true
+            }
+            _ => false,
+        };
+        if !done {
+            self.bell();
         }
     }
 
-    fn normal_insert_newline(&mut self) -> bool {
-        let mut cur_pos = self.cursor_pos;
-        cur_pos.x = if let Some(row) = self.document.row(cur_pos.y) {
-            row.len()
-        } else {
-            return false;
+    // `gq`, reflow the rows to the wrap width (79 if unset) and put the
+    // cursor on the first char of the last formatted row
+    fn format_rows(&mut self, start: usize, end: usize) {
+        let width = match self.wrap_width() {
+            0 => DEFAULT_TEXTWIDTH,
+            width => width,
         };
-        self.document.insert(&cur_pos, '\n');
-        true
+        let last = self.document.reflow(start, end, width);
+        self.cursor_pos = self.clamp_pos(Pos { x: 0, y: last });
+        self.move_cursor_thisline_first_char();
+    }
+
+    // `o` / `O`, insert in a new empty row below / above the cursor row,
+    // whatever the cursor column. An empty document gets its first row too
+    fn open_row(&mut self, below: bool) {
+        let len = self.document.len();
+        let y = if below { self.cursor_pos.y.saturating_add(1) } else { self.cursor_pos.y };
+        let y = cmp::min(y, cmp::max(len, 1));
+        if len == 0 {
+            self.document.insert_rows(0, "\n");
+        } else {
+            self.document.insert_rows(y, "");
+        }
+        if self.document.len() == len {
+            return; // refused, the document is not modifiable
+        }
+        self.cursor_pos = Pos { x: 0, y };
+        self.change_mode(Mode::Insert);
     }
 
     // wrapper for move_cursor<char>, and contrain the navigation
     // e.g. we do not allow navigate to \n (end of line)
     fn normal_move_cursor(&mut self, c: char) {
         match c {
-            'h' => {
-                // not allowing use h to move up a line
-                if self.cursor_pos.x != 0 {
-                    self.move_cursor(Key::Left);
-                }
-            }
+            // `h` / `l` only move to another row with `whichwrap`
+            'h' => self.normal_step(false, self.config.whichwrap.h),
             'j' => {
                 self.move_cursor(Key::Down);
-                self.fix_if_cursor_at_newline();  // not allowing to navigate to \n
+                if !self.config.virtualedit {
+                    self.fix_if_cursor_at_newline();  // not allowing to navigate to \n
+                }
             }
             'k' => {
                 self.move_cursor(Key::Up);
-                self.fix_if_cursor_at_newline();  // not allowing to navigate to \n
-            }
-            'l' => {
-                let Pos {x, y} = self.cursor_pos;
-                // we do not allow to navigate to \n
-                if let Some(row) = self.document.row(y) {
-                    if x < row.len().saturating_sub(1) {
-                        self.move_cursor(Key::Right)
-                    }
+                if !self.config.virtualedit {
+                    self.fix_if_cursor_at_newline();  // not allowing to navigate to \n
                 }
             }
+            'l' => self.normal_step(true, self.config.whichwrap.l),
             _ => (),
         }
     }
 
+    // one character left or right (`forward`), from the start / end of a row
+    // to the end / start of the one before / after it when it may `wrap`
+    fn normal_step(&mut self, forward: bool, wrap: bool) {
+        let Pos { x, y } = self.cursor_pos;
+        let len = self.document.row(y).map_or(0, Row::len);
+        if forward {
+            // we do not allow to navigate to \n, unless with virtualedit
+            if x < len.saturating_sub(1) || self.config.virtualedit {
+                self.move_cursor(Key::Right);
+            } else if wrap && y.saturating_add(1) < self.document.len() {
+                self.cursor_pos = Pos { x: 0, y: self.document.next_visible_row(y) };
+            }
+        } else if x > 0 {
+            self.move_cursor(Key::Left);
+        } else if wrap && y > 0 {
+            let y = self.document.prev_visible_row(y);
+            let len = self.document.row(y).map_or(0, Row::len);
+            self.cursor_pos = Pos { x: len.saturating_sub(1), y };
+        }
+    }
+
     // if we are at the last char(newline) of a line, we move back
     fn fix_if_cursor_at_newline(&mut self) {
         let Pos {x, y} = self.cursor_pos;
@@ -596,6 +3185,272 @@ impl Editor {
         }
     }
 
+    // `Ctrl-E` / `Ctrl-Y`, scroll the view by count rows, the cursor only
+    // moves when it would leave the screen
+    fn scroll_lines(&mut self, direction: SearchDirection) {
+        let height = self.view.height;
+        let document = &self.document;
+        let mut offset_y = self.offset.y;
+        let mut y = self.cursor_pos.y;
+        for _ in 0..cmp::max(self.count, 1) {
+            if direction == SearchDirection::Forward {
+                if document.next_visible_row(offset_y) >= document.len() {
+                    break;
+                }
+                offset_y = document.next_visible_row(offset_y);
+            } else {
+                if offset_y == 0 {
+                    break;
+                }
+                offset_y = document.prev_visible_row(offset_y);
+            }
+        }
+        if y < offset_y {
+            y = offset_y;
+        }
+        while document.visible_rows_between(offset_y, y) >= height {
+            y = document.prev_visible_row(y);
+        }
+        self.offset.y = offset_y;
+        self.cursor_pos = self.clamp_pos(Pos { x: self.cursor_pos.x, y });
+    }
+
+    // `:N%` / `N%`, go to the row N percent into the document (rounded up)
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    fn goto_percent(&mut self, percent: usize) {
+        let len = self.document.len();
+        let line = (cmp::min(percent, 100) * len + 99) / 100;
+        self.goto_row(line.saturating_sub(1));
+    }
+
+    // `Ctrl-W` followed by `s` / `v` (split the window / side by side),
+    // `w` / `W` (go to the next / previous window, or to window count),
+    // `h` / `j` / `k` / `l` (the window left / below / above / right),
+    // `c` (close the window) or `q` (like `:q`)
+    fn window_command(&mut self, key: Key) {
+        let key = match key {
+            Key::Left => Key::Char('h'),
+            Key::Down => Key::Char('j'),
+            Key::Up => Key::Char('k'),
+            Key::Right => Key::Char('l'),
+            key => key,
+        };
+        let count = self.windows.len();
+        let current = self.windows.current();
+        let target = match key {
+            Key::Null => return,
+            Key::Char('s' | 'S') | Key::Ctrl('s') => {
+                self.split_window(false);
+                return;
+            }
+            Key::Char('v') | Key::Ctrl('v') => {
+                self.split_window(true);
+                return;
+            }
+            Key::Char('c') => {
+                self.close_window();
+                return;
+            }
+            Key::Char('q') | Key::Ctrl('q') => {
+                self.quit(false);
+                return;
+            }
+            Key::Char('w') | Key::Ctrl('w') if self.count > 0 => Some(cmp::min(self.count, count).saturating_sub(1)),
+            Key::Char('w') | Key::Ctrl('w') => Some(current.saturating_add(1).checked_rem(count).unwrap_or(0)),
+            Key::Char('W') => Some(current.checked_sub(1).unwrap_or_else(|| count.saturating_sub(1))),
+            Key::Char(c @ ('h' | 'j' | 'k' | 'l')) => {
+                let mut moved = false;
+                for _ in 0..cmp::max(self.count, 1) {
+                    match self.window_beside(c) {
+                        Some(index) => self.goto_window(index),
+                        None => break,
+                    }
+                    moved = true;
+                }
+                if !moved {
+                    self.bell();
+                }
+                return;
+            }
+            _ => None,
+        };
+        match target {
+            Some(index) if index != current => self.goto_window(index),
+            _ => self.bell(),
+        }
+    }
+
+    // the window next to the current one on the left / below / above / on
+    // the right (`h` / `j` / `k` / `l`), the one beside the cursor
+    fn window_beside(&self, direction: char) -> Option<usize> {
+        let rects = self.window_rects();
+        let current = rects.get(self.windows.current())?;
+        let x = cmp::min(
+            self.cursor_pos
+                .x
+                .saturating_sub(self.offset.x)
+                .saturating_add(self.gutter_width())
+                .saturating_add(current.x),
+            current.x.saturating_add(current.width).saturating_sub(1),
+        );
+        let y = self
+            .document
+            .visible_rows_between(self.offset.y, self.cursor_pos.y)
+            .saturating_add(current.y);
+        let right = |rect: &Rect| rect.x.saturating_add(rect.width);
+        let bottom = |rect: &Rect| rect.y.saturating_add(rect.height);
+        rects.iter().position(|rect| {
+            let in_column = rect.x <= x && x < right(rect);
+            let in_row = rect.y <= y && y < bottom(rect);
+            match direction {
+                'h' => right(rect).saturating_add(1) == current.x && in_row,
+                'l' => right(current).saturating_add(1) == rect.x && in_row,
+                'k' => bottom(rect) == current.y && in_column,
+                'j' => bottom(current) == rect.y && in_column,
+                _ => false,
+            }
+        })
+    }
+
+    // `Ctrl-W s` / `:split` (or `Ctrl-W v` / `:vsplit`), the new window
+    // above (left) shows the same rows and becomes the current one
+    fn split_window(&mut self, vertical: bool) {
+        let rect = match self.window_rects().get(self.windows.current()) {
+            Some(rect) => *rect,
+            None => return,
+        };
+        // each window needs a column, or a row of text and its status bar
+        let room = if vertical {
+            rect.width >= 3
+        } else {
+            rect.height.saturating_add(usize::from(self.status_rows() == 0)) >= 4
+        };
+        if !room {
+            self.set_status_message("Not enough room");
+            self.bell();
+            return;
+        }
+        self.windows.split(self.current_view(), vertical);
+        self.layout();
+    }
+
+    // `Ctrl-W c` / `:close`, the last window can't be closed
+    fn close_window(&mut self) {
+        if !self.windows.close() {
+            self.set_status_message("Cannot close last window");
+            self.bell();
+            return;
+        }
+        if let Some(view) = self.windows.view(self.windows.current()) {
+            self.cursor_pos = self.clamp_pos(view.cursor);
+            self.offset = view.offset;
+            self.scrollbind = view.scrollbind;
+        }
+        self.layout();
+    }
+
+    fn goto_window(&mut self, index: usize) {
+        if let Some(view) = self.windows.switch(index, self.current_view()) {
+            self.cursor_pos = self.clamp_pos(view.cursor);
+            self.offset = view.offset;
+            self.scrollbind = view.scrollbind;
+            self.layout();
+        }
+    }
+
+    // with `scrollbind`, the other windows that have it scroll by as many
+    // rows as the current one did since `offset_y`, their cursors are kept
+    // in view
+    fn scroll_other_windows(&mut self, offset_y: usize) {
+        let current = self.windows.current();
+        let rects = self.window_rects();
+        let last = self.document.len().saturating_sub(1);
+        for (index, rect) in rects.into_iter().enumerate() {
+            let mut view = match self.windows.view(index) {
+                Some(view) if index != current && view.scrollbind => view,
+                _ => continue,
+            };
+            view.offset.y = if self.offset.y >= offset_y {
+                cmp::min(view.offset.y.saturating_add(self.offset.y.saturating_sub(offset_y)), last)
+            } else {
+                view.offset.y.saturating_sub(offset_y.saturating_sub(self.offset.y))
+            };
+            let bottom = view
+                .offset
+                .y
+                .saturating_add(self.text_rect(rect).height)
+                .saturating_sub(1);
+            view.cursor.y = cmp::min(cmp::max(view.cursor.y, view.offset.y), bottom);
+            self.windows.set_view(index, view);
+        }
+    }
+
+    // `'x` goes to the first character of the row of mark `x`, `` `x `` to
+    // its position
+    fn goto_mark(&mut self, mark: char, exact: bool) {
+        let pos = match self.marks.get(&mark) {
+            Some(pos) => *pos,
+            None => {
+                self.set_status_message("Mark not set");
+                self.bell();
+                return;
+            }
+        };
+        if pos.y >= self.document.len() {
+            self.set_status_message("Mark has invalid line number");
+            self.bell();
+        } else if exact {
+            self.cursor_pos = self.clamp_pos(pos);
+        } else {
+            self.goto_row(pos.y);
+        }
+    }
+
+    // `G` / `gg`, to the first character of row `y`
+    fn goto_row(&mut self, y: usize) {
+        self.cursor_pos = self.clamp_pos(Pos { x: 0, y });
+        self.move_cursor_thisline_first_char();
+    }
+
+    // the row given by the count (1 based), `default` without one
+    fn counted_row(&self, default: usize) -> usize {
+        if self.count > 0 {
+            cmp::min(self.count, self.document.len()).saturating_sub(1)
+        } else {
+            default
+        }
+    }
+
+    fn move_paragraph(&mut self, direction: SearchDirection) {
+        let mut y = self.cursor_pos.y;
+        for _ in 0..cmp::max(self.count, 1) {
+            y = self.document.paragraph_boundary(y, direction);
+        }
+        self.cursor_pos = Pos { x: 0, y };
+    }
+
+    // the rows from the cursor's to the count-th paragraph boundary, the
+    // blank row the motion stops on is left out
+    fn paragraph_rows(&self, direction: SearchDirection) -> (usize, usize) {
+        let y = self.cursor_pos.y;
+        let mut target = y;
+        for _ in 0..cmp::max(self.count, 1) {
+            target = self.document.paragraph_boundary(target, direction);
+        }
+        let is_blank = self.document.row(target).map_or(false, Row::is_blank);
+        if direction == SearchDirection::Forward {
+            (y, if is_blank && target > y { target.saturating_sub(1) } else { target })
+        } else {
+            (if is_blank && target < y { target.saturating_add(1) } else { target }, y)
+        }
+    }
+
+    fn move_sentence(&mut self, direction: SearchDirection) {
+        for _ in 0..cmp::max(self.count, 1) {
+            self.cursor_pos = self.document.sentence_start(&self.cursor_pos, direction);
+        }
+    }
+
     fn move_cursor_thisline_end(&mut self) {
         let mut pos = &mut self.cursor_pos;
         if let Some(row) = self.document.row(pos.y) {
@@ -621,21 +3476,291 @@ impl Editor {
     }
 
 
-    fn parse_command(&mut self) {
+    fn parse_command(&mut self) -> Result<(), std::io::Error> {
         let cmd = self
-            .prompt(":", |_, _, _|{})
+            .prompt(":", |editor, _, cmd| editor.preview_substitute(cmd))
             .unwrap_or(None);
-        if let Some(cmd) = cmd {
-            match &cmd as &str {
-                "w" => self.save(),
+        self.preview_substitute("");
+        if let Some(substitute) = cmd.as_deref().and_then(Substitute::parse) {
+            self.substitute(&substitute)?;
+            self.last_substitute = Some(substitute);
+        } else if let Some(percent) = cmd
+            .as_deref()
+            .and_then(|cmd| cmd.strip_suffix('%'))
+            .and_then(|percent| percent.parse().ok())
+        {
+            self.goto_percent(percent);
+        } else if let Some(cmd) = cmd {
+            let (name, args) = cmd.split_once(' ').unwrap_or((&cmd, ""));
+            match name {
+                "set" | "se" => self.set_option(args),
+                "ascii" | "as" => self.show_char_code(),
+                "w" => {
+                    self.save(false);
+                }
+                "w!" => {
+                    self.save(true);
+                }
                 "q" => self.quit(false),
                 "q!" => self.quit(true),
                 "wq" => {
-                    self.save();
-                    self.quit(false);
+                    if self.save(false) {
+                        self.quit(false);
+                    }
+                }
+                "x" | "xit" => self.exit(),
+                "b#" | "e#" | "e!#" => self.edit_alternate(name.contains('!')),
+                "b" | "buffer" | "e" | "edit" | "e!" | "edit!" if args == "#" => {
+                    self.edit_alternate(name.ends_with('!'));
+                }
+                "e" | "edit" | "e!" | "edit!" if !args.is_empty() => self.edit(args, name.ends_with('!')),
+                "e" | "edit" | "e!" | "edit!" => self.reload(name.ends_with('!')),
+                "enew" | "ene" | "enew!" | "ene!" => self.edit_new(name.ends_with('!')),
+                "make" | "mak" => self.make(args)?,
+                "grep" | "gr" => self.grep(args)?,
+                "cnext" | "cn" => self.quickfix_step(true),
+                "cprevious" | "cp" | "cNext" | "cN" => self.quickfix_step(false),
+                "cc" => self.goto_quickfix(),
+                "registers" | "reg" | "display" | "di" => self.show_registers(),
+                "marks" => self.show_marks(),
+                "split" | "sp" => self.split_window(false),
+                "vsplit" | "vs" => self.split_window(true),
+                "Files" => self.find_files()?,
+                "close" | "clo" | "close!" | "clo!" => self.close_window(),
+                "put" | "pu" | "put!" | "pu!" => self.put_command(args, !name.ends_with('!')),
+                "TrimWhitespace" => {
+                    let count = self.document.trim_trailing();
+                    self.cursor_pos = self.clamp_pos(self.cursor_pos);
+                    self.set_status_message(&format!("Trimmed trailing whitespace on {} row(s)", count));
+                }
+                _ => {
+                    self.set_status_message("Unknown command!");
+                    self.bell();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // `&` repeats the last substitution on the cursor row without its
+    // flags, `g&` on every row with its flags
+    fn repeat_substitute(&mut self, whole_file: bool) -> Result<(), std::io::Error> {
+        let mut substitute = match self.last_substitute.clone() {
+            Some(substitute) => substitute,
+            None => {
+                self.set_status_message("No previous substitute");
+                self.bell();
+                return Ok(());
+            }
+        };
+        substitute.whole_file = whole_file;
+        if !whole_file {
+            substitute.global = false;
+            substitute.confirm = false;
+        }
+        self.substitute(&substitute)
+    }
+
+    // highlight what the `:s` being typed would replace
+    fn preview_substitute(&mut self, cmd: &str) {
+        // patterns that are not complete yet may not be valid
+        let preview = Substitute::parse(cmd)
+            .filter(|substitute| !substitute.pattern.is_empty())
+            .and_then(|substitute| {
+                let regex = substitute.regex(self.config.ignore_case(&substitute.pattern)).ok()?;
+                Some((self.substitute_rows(&substitute), regex))
+            });
+        if preview.is_none() && self.highlighted_rows.is_none() {
+            return;
+        }
+        self.highlighted_rows = preview.as_ref().map(|(rows, _)| *rows);
+        self.highlighted_word = preview.map(|(_, regex)| regex);
+        self.document.unhighlight_rows(0);
+    }
+
+    // the rows (start, end) a substitution applies to
+    fn substitute_rows(&self, substitute: &Substitute) -> (usize, usize) {
+        if substitute.whole_file {
+            (0, self.document.len().saturating_sub(1))
+        } else {
+            (self.cursor_pos.y, self.cursor_pos.y)
+        }
+    }
+
+    fn substitute(&mut self, substitute: &Substitute) -> Result<(), std::io::Error> {
+        let regex = match substitute.regex(self.config.ignore_case(&substitute.pattern)) {
+            Ok(regex) => regex,
+            Err(msg) => {
+                self.set_status_message(&msg);
+                self.bell();
+                return Ok(());
+            }
+        };
+        let (mut y, mut end) = self.substitute_rows(substitute);
+        let mut count: usize = 0;
+        let mut rows: usize = 0;
+        let mut last_row = None;
+        let mut found = false;
+        let mut confirm = substitute.confirm;
+        // a replacement with line breaks adds rows, which moves the end
+        'rows: while y <= end {
+            if !confirm {
+                let len = self.document.len();
+                let replaced = self.document.substitute(y, &regex, substitute.global, |captures| {
+                    substitute.expand(captures)
+                });
+                if replaced > 0 {
+                    let added = self.document.len().saturating_sub(len);
+                    y = y.saturating_add(added);
+                    end = end.saturating_add(added);
+                    count = count.saturating_add(replaced);
+                    rows = rows.saturating_add(1);
+                    last_row = Some(y);
                 }
-                _ => self.set_status_message("Unknown command!")
+                y = y.saturating_add(1);
+                continue;
+            }
+            // with `c`, each match is replaced on its own once confirmed,
+            // until `a` replaces the remaining ones without asking
+            let mut x = 0;
+            let mut replaced_in_row = false;
+            while let Some((range, replacement)) = self.document.row(y).and_then(|row| {
+                let (range, captures) = row.find_regex(&regex, x)?;
+                Some((range, substitute.expand(&captures)))
+            }) {
+                found = true;
+                let at = Pos { x: range.start, y };
+                let answer = if confirm {
+                    self.confirm_substitution(at, &regex, substitute)?
+                } else {
+                    Some('y')
+                };
+                match answer {
+                    Some(answer @ ('y' | 'a')) => {
+                        confirm = confirm && answer == 'y';
+                        self.document.delete_range(&at, &Pos { x: range.end, y });
+                        let after = self.document.insert_str(&at, &replacement);
+                        end = end.saturating_add(after.y.saturating_sub(y));
+                        y = after.y;
+                        x = after.x;
+                        count = count.saturating_add(1);
+                        replaced_in_row = true;
+                        last_row = Some(y);
+                    }
+                    Some('n') => x = range.end,
+                    Some('q') => break 'rows,
+                    _ => continue,
+                }
+                // an empty match is stepped over
+                if range.is_empty() {
+                    x = x.saturating_add(1);
+                }
+                if !substitute.global {
+                    break;
+                }
+            }
+            if replaced_in_row {
+                rows = rows.saturating_add(1);
+            }
+            y = y.saturating_add(1);
+        }
+        if substitute.confirm {
+            self.highlighted_word = None;
+            self.highlighted_rows = None;
+            self.document.unhighlight_rows(0);
+            self.set_status_message("");
+        }
+        match last_row {
+            Some(y) => {
+                self.cursor_pos.y = y;
+                self.move_cursor_thisline_first_char();
+                if count > 1 {
+                    let lines = if rows == 1 { "line" } else { "lines" };
+                    self.set_status_message(&format!("{} substitutions on {} {}", count, rows, lines));
+                }
+            }
+            None if found => (),
+            None => {
+                self.set_status_message(&format!("Pattern not found: {}", substitute.pattern));
+                self.bell();
+            }
+        }
+        Ok(())
+    }
+
+    // show the match at `at` and ask whether to replace it: 'y' (yes),
+    // 'n' (no), 'a' (this one and all the remaining ones) or 'q' (quit,
+    // also Esc), None for any other key
+    fn confirm_substitution(
+        &mut self,
+        at: Pos,
+        regex: &Regex,
+        substitute: &Substitute,
+    ) -> Result<Option<char>, std::io::Error> {
+        self.cursor_pos = at;
+        self.scroll();
+        self.highlighted_word = Some(regex.clone());
+        self.highlighted_rows = Some((at.y, at.y));
+        self.document.unhighlight_rows(0);
+        self.set_status_message(&format!("replace with {} (y/n/a/q)?", substitute.replacement));
+        self.refresh_screen()?;
+        Ok(match self.read_key()? {
+            Key::Char(c @ ('y' | 'n' | 'a' | 'q')) => Some(c),
+            // a paste stops too
+            Key::Esc | Key::Ctrl('c') | Key::Null => Some('q'),
+            _ => None,
+        })
+    }
+
+    fn set_option(&mut self, args: &str) {
+        // `modifiable`, `bomb` and `foldmethod` belong to the document,
+        // `scrollbind` to the current window, the other options to the editor
+        let mut options = Vec::new();
+        for arg in args.split_whitespace() {
+            match arg.split_once('=') {
+                Some((name @ ("foldmethod" | "fdm"), value)) => match FoldMethod::parse(value) {
+                    Some(method) => self.document.set_foldmethod(method),
+                    None => {
+                        self.set_status_message(&format!("Invalid value for {}: {}", name, value));
+                        self.bell();
+                    }
+                },
+                _ => match arg {
+                    "modifiable" | "ma" => self.document.set_modifiable(true),
+                    "nomodifiable" | "noma" => self.document.set_modifiable(false),
+                    "bomb" => self.document.set_bom(true),
+                    "nobomb" => self.document.set_bom(false),
+                    "scrollbind" | "scb" => self.scrollbind = true,
+                    "noscrollbind" | "noscb" => self.scrollbind = false,
+                    _ => options.push(arg),
+                },
+            }
+        }
+        if let Err(msg) = self.config.set(&options.join(" ")) {
+            self.set_status_message(&msg);
+            self.bell();
+        }
+        if self.config.spell && self.dictionary.is_none() {
+            self.dictionary = Some(Dictionary::load());
+        }
+        Terminal::cursor_style(self.cursor_style());
+        // options may change how rows are highlighted
+        self.document.unhighlight_rows(0);
+    }
+
+    // `zg`, add the word under the cursor to the dictionary
+    fn spell_add_word(&mut self) {
+        let word = self
+            .document
+            .row(self.cursor_pos.y)
+            .and_then(|row| row.word_at(self.cursor_pos.x));
+        match (word, self.dictionary.as_mut()) {
+            (Some(word), Some(dictionary)) => {
+                dictionary.add(&word);
+                self.document.unhighlight_rows(0);
+                self.set_status_message(&format!("Word '{}' added to the dictionary", word));
             }
+            _ => self.bell(),
         }
     }
 
@@ -644,7 +3769,214 @@ impl Editor {
     }
 }
 
+// the branch checked out in the git repository the working directory is in,
+// from its `.git/HEAD` (`ref: refs/heads/{branch}`), or the start of the
+// commit hash when detached
+fn git_branch() -> Option<String> {
+    let dir = env::current_dir().ok()?;
+    let git = dir.ancestors().map(|dir| dir.join(".git")).find(|git| git.exists())?;
+    // in a worktree `.git` is a file with `gitdir: {path}`
+    let git = match fs::read_to_string(&git) {
+        Ok(text) => git.parent()?.join(text.trim().strip_prefix("gitdir: ")?),
+        Err(_) => git,
+    };
+    let head = fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(name) => Some(name.strip_prefix("refs/heads/").unwrap_or(name).to_owned()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+// the byte where the word to complete starts in `cmd` and its candidates,
+// the path after `:e` or else the command name
+fn command_candidates(cmd: &str) -> (usize, Vec<String>) {
+    match cmd.split_once(' ') {
+        Some(("e" | "edit" | "e!" | "edit!", path)) => {
+            (cmd.len().saturating_sub(path.len()), path_candidates(path))
+        }
+        Some(_) => (cmd.len(), Vec::new()),
+        None => {
+            let names = COMMANDS.iter().filter(|name| name.starts_with(cmd));
+            (0, names.map(|name| (*name).to_owned()).collect())
+        }
+    }
+}
+
+// the entries of the directory of `path` whose name starts with the rest of
+// it, directories end with a '/' and hidden files are only listed when the
+// name starts with a '.'
+fn path_candidates(path: &str) -> Vec<String> {
+    let (dir, name) = match path.rfind('/') {
+        Some(slash) => path.split_at(slash.saturating_add(1)),
+        None => ("", path),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+// the character a key sends to the terminal, for `Ctrl-V`. Enter inserts a
+// carriage return as it does not split the row
+fn literal_char(key: Key) -> Option<char> {
+    match key {
+        Key::Char('\n') => Some('\r'),
+        Key::Char(c) => Some(c),
+        Key::Esc => Some('\x1b'),
+        Key::Backspace => Some('\x7f'),
+        // `Ctrl-A`..`Ctrl-Z` are 0x01..0x1a, termion reads 0x1c..0x1f as
+        // `Ctrl-4`..`Ctrl-7`
+        Key::Ctrl(c @ 'a'..='z') => char::from_u32(u32::from(c).saturating_sub(u32::from('a')).saturating_add(1)),
+        Key::Ctrl(c @ '4'..='7') => char::from_u32(u32::from(c).saturating_sub(u32::from('4')).saturating_add(0x1c)),
+        _ => None,
+    }
+}
+
 fn die(e: &std::io::Error) {
     Terminal::clear_screen();
     panic!("{}", e);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an editor on `lines` after typing `keys`, each one on its own
+    fn type_keys(lines: &[&str], keys: &[&str]) -> Editor {
+        let mut editor = Editor::new(Terminal::headless(keys), Document::from_lines(lines), String::new());
+        // until there are no keys left
+        while editor.process_keypress().is_ok() {}
+        editor
+    }
+
+    fn rows(editor: &Editor) -> Vec<&str> {
+        (0..editor.document.len())
+            .filter_map(|y| editor.document.row(y))
+            .map(Row::as_str)
+            .collect()
+    }
+
+    fn cursor(editor: &Editor) -> (usize, usize) {
+        (editor.cursor_pos.x, editor.cursor_pos.y)
+    }
+
+    #[test]
+    fn open_row_from_the_first_column() {
+        let editor = type_keys(&["one", "two"], &["o", "new"]);
+        assert_eq!(rows(&editor), ["one", "new", "two"]);
+        assert!(editor.mode == Mode::Insert);
+        assert_eq!(cursor(&editor), (3, 1));
+    }
+
+    #[test]
+    fn open_row_from_the_middle_of_a_row() {
+        let editor = type_keys(&["one", "two"], &["l", "o", "new"]);
+        assert_eq!(rows(&editor), ["one", "new", "two"]);
+        assert_eq!(cursor(&editor), (3, 1));
+    }
+
+    #[test]
+    fn open_row_below_the_last_row() {
+        let editor = type_keys(&["one", "two"], &["j", "$", "o", "new", "\x1b"]);
+        assert_eq!(rows(&editor), ["one", "two", "new"]);
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(cursor(&editor), (2, 2));
+    }
+
+    #[test]
+    fn yank_two_rows_and_put_them_below() {
+        let editor = type_keys(&["one", "two", "three"], &["2", "y", "y", "j", "j", "p"]);
+        assert_eq!(rows(&editor), ["one", "two", "three", "one", "two"]);
+        assert_eq!(cursor(&editor), (0, 3));
+    }
+
+    #[test]
+    fn scrollbind_is_set_per_window() {
+        let editor = type_keys(&["row"; 100], &[":", "split\r", ":", "set scb\r", "\x05", "\x05"]);
+        assert_eq!(editor.offset.y, 2);
+        // the other window does not have scrollbind
+        assert_eq!(editor.windows.view(1).map(|view| view.offset.y), Some(0));
+    }
+
+    #[test]
+    fn scrollbind_windows_scroll_together() {
+        let editor = type_keys(&["row"; 100], &[":", "set scb\r", ":", "split\r", "\x05", "\x05"]);
+        assert_eq!(editor.offset.y, 2);
+        assert_eq!(editor.windows.view(1).map(|view| view.offset.y), Some(2));
+    }
+
+    #[test]
+    fn upper_case_inner_word() {
+        let editor = type_keys(&["hello world"], &["$", "g", "U", "i", "w"]);
+        assert_eq!(rows(&editor), ["hello WORLD"]);
+        assert_eq!(cursor(&editor), (6, 0));
+    }
+
+    #[test]
+    fn lower_case_row() {
+        let editor = type_keys(&["Hello World", "KEEP"], &["$", "g", "u", "u"]);
+        assert_eq!(rows(&editor), ["hello world", "KEEP"]);
+    }
+
+    #[test]
+    fn toggle_case_to_end_of_row() {
+        let editor = type_keys(&["Hello World"], &["l", "g", "~", "$"]);
+        assert_eq!(rows(&editor), ["HELLO wORLD"]);
+        assert_eq!(cursor(&editor), (1, 0));
+    }
+
+    #[test]
+    fn visual_bell_flashes_without_waiting() {
+        let editor = type_keys(&["one"], &[":", "set vb\r", ":", "nosuchcommand\r", "x"]);
+        // the key after the failed command is read during the flash
+        assert_eq!(rows(&editor), ["ne"]);
+        assert!(editor.bell.is_some());
+    }
+
+    #[test]
+    fn paste_in_normal_mode_is_inserted_before_the_cursor() {
+        let editor = type_keys(&["ab"], &["l", "\x1b[200~xy\x1b[201~"]);
+        assert_eq!(rows(&editor), ["axyb"]);
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(cursor(&editor), (2, 0));
+        let editor = type_keys(&["ab"], &["l", "\x1b[200~xy\x1b[201~", "u"]);
+        assert_eq!(rows(&editor), ["ab"]);
+    }
+
+    #[test]
+    fn paste_cancels_a_pending_command() {
+        let editor = type_keys(&["ab"], &["d", "\x1b[200~x\ry\x1b[201~"]);
+        assert_eq!(rows(&editor), ["x", "yab"]);
+    }
+
+    #[test]
+    fn paste_in_a_prompt_is_added_to_the_line() {
+        let editor = type_keys(&["ab", "xy"], &["/", "\x1b[200~x\x1b[201~", "\r"]);
+        assert_eq!(cursor(&editor), (0, 1));
+    }
+
+    #[test]
+    fn search_wraps_around_with_or_without_incsearch() {
+        for set in ["set is\r", "set nois\r"] {
+            let editor = type_keys(&["foo bar", "baz"], &[":", set, "j", "/", "b", "a", "\r"]);
+            // the match at the cursor, then one wrapping to the top
+            assert_eq!(cursor(&editor), (0, 1));
+            let editor = type_keys(&["foo bar", "baz"], &[":", set, "j", "/", "f", "o", "o", "\r"]);
+            assert_eq!(cursor(&editor), (0, 0));
+        }
+    }
+}
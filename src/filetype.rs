@@ -121,6 +121,20 @@ impl FileType {
     pub fn options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
+
+    // keywords that introduce the name they are followed by, for `gd`
+    pub fn definition_keywords(&self) -> &[&str] {
+        match &self.name[..] {
+            "Rust" => &[
+                "fn", "let", "struct", "enum", "union", "trait", "type", "const", "static", "mod",
+                "macro_rules!",
+            ],
+            _ => &[
+                "def", "fn", "func", "function", "class", "struct", "enum", "interface", "type",
+                "let", "var", "const", "define",
+            ],
+        }
+    }
 }
 
 impl HighlightingOptions {
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::Path;
+
+// the files under the working directory for `:Files`, filtered by what's
+// typed, best match first. `index` is the selected one
+#[derive(Default)]
+pub struct Finder {
+    files: Vec<String>,
+    matches: Vec<String>,
+    index: usize,
+}
+
+impl Finder {
+    pub fn new(dir: &str, depth: usize) -> Self {
+        let files = files(dir, depth);
+        Self {
+            matches: files.clone(),
+            files,
+            index: 0,
+        }
+    }
+
+    pub fn matches(&self) -> &[String] {
+        &self.matches
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn selected(&self) -> Option<&String> {
+        self.matches.get(self.index)
+    }
+
+    // keep the files matching `query`, the best ones first
+    pub fn filter(&mut self, query: &str) {
+        let mut scored: Vec<(usize, &String)> = self
+            .files
+            .iter()
+            .filter_map(|file| Some((score(query, file)?, file)))
+            .collect();
+        // stable, so equal scores stay in path order
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+        self.matches = scored.into_iter().map(|(_, file)| file.clone()).collect();
+        self.index = 0;
+    }
+
+    // select the next (or previous) match, wrapping around
+    pub fn step(&mut self, forward: bool) {
+        let len = self.matches.len();
+        self.index = if forward {
+            self.index.saturating_add(1).checked_rem(len).unwrap_or(0)
+        } else {
+            self.index.checked_sub(1).unwrap_or_else(|| len.saturating_sub(1))
+        };
+    }
+}
+
+// the files under `dir`, at most `depth` directories down, in path order.
+// Hidden files and directories (e.g. `.git`) are skipped
+pub fn files(dir: &str, depth: usize) -> Vec<String> {
+    let mut files = Vec::new();
+    list_files(Path::new(dir), depth, &mut files);
+    files.sort();
+    files
+}
+
+fn list_files(dir: &Path, depth: usize, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            if depth > 0 {
+                list_files(&path, depth.saturating_sub(1), files);
+            }
+        } else {
+            let path = path.to_string_lossy().into_owned();
+            files.push(path.strip_prefix("./").map_or(path.clone(), str::to_owned));
+        }
+    }
+}
+
+// how well `file` matches `query`, None if the characters of `query` are not
+// all in it in order. Ignores case unless `query` has an uppercase letter.
+// Characters following each other, or at the start of a name or word, score
+// more
+#[allow(clippy::integer_arithmetic)]
+fn score(query: &str, file: &str) -> Option<usize> {
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let fold = |c: char| if ignore_case { c.to_ascii_lowercase() } else { c };
+    let mut query = query.chars().map(fold).peekable();
+    let mut score = 0;
+    let mut before: Option<char> = None;
+    let mut after_match = false;
+    for c in file.chars() {
+        let wanted = match query.peek() {
+            Some(wanted) => *wanted,
+            None => break,
+        };
+        if fold(c) == wanted {
+            query.next();
+            score += 1;
+            if after_match {
+                score += 4;
+            }
+            if before.map_or(true, |before| matches!(before, '/' | '_' | '-' | '.' | ' ')) {
+                score += 2;
+            }
+            after_match = true;
+        } else {
+            after_match = false;
+        }
+        before = Some(c);
+    }
+    if query.peek().is_some() {
+        return None;
+    }
+    // shorter paths first among equal matches
+    Some(score * 1000 + 999usize.saturating_sub(file.len()))
+}
@@ -0,0 +1,218 @@
+use std::cmp;
+use std::iter::{Chain, Rev};
+use std::ops::{Index, IndexMut, Range};
+use std::slice;
+
+// a sequence with a movable gap: the elements before the gap are kept in
+// order in `before`, the ones after it in reverse order in `after`, so that
+// both ends of the gap are the ends of a Vec. Inserting or removing at the
+// gap is O(1), moving the gap costs the distance it moves, which keeps
+// repeated edits around the same place cheap in long sequences
+pub struct GapBuffer<T> {
+    before: Vec<T>,
+    after: Vec<T>,
+}
+
+impl<T> Default for GapBuffer<T> {
+    fn default() -> Self {
+        Self {
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+}
+
+impl<T> FromIterator<T> for GapBuffer<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            before: iter.into_iter().collect(),
+            after: Vec::new(),
+        }
+    }
+}
+
+impl<T> GapBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.before.len().saturating_add(self.after.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        match index.checked_sub(self.before.len()) {
+            None => self.before.get(index),
+            Some(index) => self
+                .after
+                .len()
+                .checked_sub(index.saturating_add(1))
+                .and_then(|index| self.after.get(index)),
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        match index.checked_sub(self.before.len()) {
+            None => self.before.get_mut(index),
+            Some(index) => self
+                .after
+                .len()
+                .checked_sub(index.saturating_add(1))
+                .and_then(move |index| self.after.get_mut(index)),
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.move_gap(self.len());
+        self.before.push(value);
+    }
+
+    // an `index` past the end appends
+    pub fn insert(&mut self, index: usize, value: T) {
+        self.move_gap(index);
+        self.before.push(value);
+    }
+
+    // None if `index` is past the end
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        self.move_gap(index);
+        self.after.pop()
+    }
+
+    // insert the elements of `values` in order before `index`
+    pub fn insert_many<I: IntoIterator<Item = T>>(&mut self, index: usize, values: I) {
+        self.move_gap(index);
+        self.before.extend(values);
+    }
+
+    // remove the elements in `range`, clamped to the end
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        let end = cmp::min(range.end, self.len());
+        if range.start >= end {
+            return;
+        }
+        self.move_gap(range.start);
+        let count = end.saturating_sub(range.start);
+        self.after.truncate(self.after.len().saturating_sub(count));
+    }
+
+    pub fn iter(&self) -> Chain<slice::Iter<'_, T>, Rev<slice::Iter<'_, T>>> {
+        self.before.iter().chain(self.after.iter().rev())
+    }
+
+    pub fn iter_mut(&mut self) -> Chain<slice::IterMut<'_, T>, Rev<slice::IterMut<'_, T>>> {
+        self.before.iter_mut().chain(self.after.iter_mut().rev())
+    }
+
+    // move the gap right before the element at `index`
+    fn move_gap(&mut self, index: usize) {
+        let index = cmp::min(index, self.len());
+        if index < self.before.len() {
+            let moved = self.before.drain(index..).rev();
+            self.after.extend(moved);
+        } else {
+            let count = index.saturating_sub(self.before.len());
+            let start = self.after.len().saturating_sub(count);
+            let moved = self.after.drain(start..).rev();
+            self.before.extend(moved);
+        }
+    }
+}
+
+impl<T> Index<usize> for GapBuffer<T> {
+    type Output = T;
+
+    #[allow(clippy::expect_used)]
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+impl<T> IndexMut<usize> for GapBuffer<T> {
+    #[allow(clippy::expect_used)]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        self.get_mut(index).expect("index out of bounds")
+    }
+}
+
+impl<'a, T> IntoIterator for &'a GapBuffer<T> {
+    type Item = &'a T;
+    type IntoIter = Chain<slice::Iter<'a, T>, Rev<slice::Iter<'a, T>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut GapBuffer<T> {
+    type Item = &'a mut T;
+    type IntoIter = Chain<slice::IterMut<'a, T>, Rev<slice::IterMut<'a, T>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(values: &[u32]) -> GapBuffer<u32> {
+        values.iter().copied().collect()
+    }
+
+    fn values(buffer: &GapBuffer<u32>) -> Vec<u32> {
+        buffer.iter().copied().collect()
+    }
+
+    #[test]
+    fn get_on_both_sides_of_the_gap() {
+        let mut buffer = buffer(&[0, 1, 2, 3, 4]);
+        buffer.move_gap(2);
+        assert_eq!(buffer.get(1), Some(&1));
+        assert_eq!(buffer.get(2), Some(&2));
+        assert_eq!(buffer.get(4), Some(&4));
+        assert_eq!(buffer.get(5), None);
+        if let Some(value) = buffer.get_mut(3) {
+            *value = 30;
+        }
+        assert_eq!(values(&buffer), [0, 1, 2, 30, 4]);
+    }
+
+    #[test]
+    fn moving_the_gap_keeps_the_order() {
+        let mut buffer = buffer(&[0, 1, 2, 3, 4]);
+        for index in [3, 0, 5, 1, 9, 2] {
+            buffer.move_gap(index);
+            assert_eq!(values(&buffer), [0, 1, 2, 3, 4]);
+            assert_eq!(buffer.len(), 5);
+        }
+    }
+
+    #[test]
+    fn insert_many_before_an_index() {
+        let mut buffer = buffer(&[0, 1, 2]);
+        buffer.move_gap(3);
+        buffer.insert_many(1, [10, 11]);
+        assert_eq!(values(&buffer), [0, 10, 11, 1, 2]);
+        buffer.insert_many(9, [12]);
+        assert_eq!(values(&buffer), [0, 10, 11, 1, 2, 12]);
+        buffer.insert(0, 13);
+        assert_eq!(values(&buffer), [13, 0, 10, 11, 1, 2, 12]);
+    }
+
+    #[test]
+    fn remove_range_clamped_to_the_end() {
+        let mut buffer = buffer(&[0, 1, 2, 3, 4, 5]);
+        buffer.move_gap(5);
+        buffer.remove_range(1..3);
+        assert_eq!(values(&buffer), [0, 3, 4, 5]);
+        buffer.remove_range(2..9);
+        assert_eq!(values(&buffer), [0, 3]);
+        buffer.remove_range(1..1);
+        assert_eq!(values(&buffer), [0, 3]);
+        assert_eq!(buffer.remove(0), Some(0));
+        assert_eq!(buffer.remove(1), None);
+        assert_eq!(values(&buffer), [3]);
+    }
+}
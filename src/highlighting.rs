@@ -12,6 +12,9 @@ pub enum Type {
     PrimaryKeywords,
     SecondaryKeywords,
     Function,
+    Misspelled,
+    Bracket(usize), // with `rainbow`, the nesting depth
+    UnmatchedBracket,
 }
 
 //TODO: highlight for functions (var.func()) (between sep and '(')
@@ -25,6 +28,23 @@ const COLOR_PRIMARYKW: color::Rgb = color::Rgb(221, 160, 221);
 const COLOR_SECONDARYKW: color::Rgb = color::Rgb(255, 250, 205);
 const COLOR_NOCOLOR: color::Rgb = color::Rgb(255, 255, 255);
 const COLOR_FUNCTION: color::Rgb = color::Rgb(0, 229, 238);
+const COLOR_MISSPELLED: color::Rgb = color::Rgb(255, 85, 85);
+const COLOR_BRACKETS: [color::Rgb; 6] = [
+    color::Rgb(255, 215, 0),
+    color::Rgb(218, 112, 214),
+    color::Rgb(23, 159, 255),
+    color::Rgb(127, 255, 0),
+    color::Rgb(255, 140, 0),
+    color::Rgb(64, 224, 208),
+];
+const COLOR_UNMATCHED_BRACKET: color::Rgb = color::Rgb(255, 0, 0);
+pub const COLOR_SIGN_ADDED: color::Rgb = color::Rgb(80, 200, 120);
+pub const COLOR_SIGN_MODIFIED: color::Rgb = color::Rgb(255, 165, 0);
+pub const COLOR_SELECTION: color::Rgb = color::Rgb(68, 68, 68);
+pub const COLOR_CURSORLINE: color::Rgb = color::Rgb(40, 40, 40);
+pub const COLOR_COLORCOLUMN: color::Rgb = color::Rgb(88, 40, 40);
+pub const COLOR_FOLD_FG: color::Rgb = color::Rgb(150, 150, 150);
+pub const COLOR_FOLD_BG: color::Rgb = color::Rgb(48, 48, 48);
 
 impl Type {
     pub fn to_color(self) -> impl color::Color {
@@ -37,6 +57,13 @@ impl Type {
            Type::PrimaryKeywords => COLOR_PRIMARYKW,
            Type::SecondaryKeywords => COLOR_SECONDARYKW,
            Type::Function => COLOR_FUNCTION,
+           Type::Misspelled => COLOR_MISSPELLED,
+           Type::Bracket(depth) => depth
+               .checked_rem(COLOR_BRACKETS.len())
+               .and_then(|i| COLOR_BRACKETS.get(i))
+               .copied()
+               .unwrap_or(COLOR_NOCOLOR),
+           Type::UnmatchedBracket => COLOR_UNMATCHED_BRACKET,
            Type::None => COLOR_NOCOLOR,
         }
     }
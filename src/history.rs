@@ -0,0 +1,473 @@
+use crate::Pos;
+use std::cmp;
+use std::ops::Range;
+
+// rows `at..at + len` of the document stand where `rows` were, applying the
+// edit swaps them, which gives the edit that undoes it
+struct Edit {
+    at: usize,
+    len: usize,
+    rows: Vec<String>,
+}
+
+// a state of the document, made by a change to its `parent` state
+struct Node {
+    parent: usize,
+    // the change from the parent state, as the rows it replaced while the
+    // document is in this state (or below it), as the rows it put while not
+    edits: Vec<Edit>,
+    cursor: Pos,        // where the cursor was when the document left this state
+    change_cursor: Pos, // where it was when the change making this state started
+    next: Option<usize>, // the child redo goes to, the last one made or left
+}
+
+// an undo tree of whole changes. Every change makes a new state, numbered by
+// its revision, from the current one: undo goes to the parent state, redo to
+// the child last visited, and `g-` / `g+` to the states made just before /
+// after in time, branches included. Only the rows a change replaced are
+// kept, so a change to a big document costs what it changed. The document
+// is modified when its revision is not the one that was last saved
+pub struct History {
+    nodes: Vec<Node>, // indexed by revision, the first is the content as opened
+    revision: usize,
+    saved_revision: usize,
+    // set by `start_change`, the cursor to restore when undoing the change
+    pending: Option<Pos>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: 0,
+                edits: Vec::new(),
+                cursor: Pos::default(),
+                change_cursor: Pos::default(),
+                next: None,
+            }],
+            revision: 0,
+            saved_revision: 0,
+            pending: None,
+        }
+    }
+}
+
+impl History {
+    // the next modification of the document starts a new change
+    pub fn start_change(&mut self, cursor: Pos) {
+        self.pending = Some(cursor);
+    }
+
+    // called before each modification: `replaced` rows are about to be
+    // replaced by `len` rows, `rows` gives their text. The first
+    // modification of a change makes a new state, the next ones add to it
+    pub fn record<F>(&mut self, replaced: Range<usize>, len: usize, rows: F)
+    where
+        F: FnOnce() -> Vec<String>,
+    {
+        let cursor = match self.pending.take() {
+            Some(cursor) => Some(cursor),
+            // a modification without a change started right after saving
+            None if self.revision == self.saved_revision || self.revision == 0 => Some(Pos::default()),
+            None => None,
+        };
+        if let Some(cursor) = cursor {
+            let revision = self.nodes.len();
+            if let Some(node) = self.nodes.get_mut(self.revision) {
+                node.cursor = cursor;
+                node.next = Some(revision);
+            }
+            self.nodes.push(Node {
+                parent: self.revision,
+                edits: Vec::new(),
+                cursor,
+                change_cursor: cursor,
+                next: None,
+            });
+            self.revision = revision;
+        }
+        let edits = match self.nodes.get_mut(self.revision) {
+            Some(node) => &mut node.edits,
+            None => return,
+        };
+        // rows the change already put are only resized (e.g. typing in a row)
+        if let Some(last) = edits.last_mut() {
+            if last.at <= replaced.start && replaced.end <= last.at.saturating_add(last.len) {
+                last.len = last.len.saturating_sub(replaced.len()).saturating_add(len);
+                return;
+            }
+        }
+        edits.push(Edit {
+            at: replaced.start,
+            len,
+            rows: rows(),
+        });
+    }
+
+    // to the parent state, `replace(at, len, rows)` puts `rows` in place of
+    // rows `at..at + len` of the document and gives the rows it took out.
+    // Returns where to put the cursor
+    pub fn undo<F>(&mut self, cursor: Pos, replace: F) -> Option<Pos>
+    where
+        F: FnMut(usize, usize, Vec<String>) -> Vec<String>,
+    {
+        let (parent, change_cursor) = self
+            .nodes
+            .get(self.revision)
+            .filter(|_| self.revision > 0)
+            .map(|node| (node.parent, node.change_cursor))?;
+        self.goto(parent, cursor, replace)?;
+        Some(change_cursor)
+    }
+
+    pub fn redo<F>(&mut self, cursor: Pos, replace: F) -> Option<Pos>
+    where
+        F: FnMut(usize, usize, Vec<String>) -> Vec<String>,
+    {
+        let next = self.nodes.get(self.revision)?.next?;
+        self.goto(next, cursor, replace)
+    }
+
+    // `g-` / `g+`, to the state made before / after the current one
+    pub fn step_in_time<F>(&mut self, forward: bool, cursor: Pos, replace: F) -> Option<Pos>
+    where
+        F: FnMut(usize, usize, Vec<String>) -> Vec<String>,
+    {
+        let revision = if forward {
+            self.revision.saturating_add(1)
+        } else {
+            self.revision.checked_sub(1)?
+        };
+        self.goto(revision, cursor, replace)
+    }
+
+    // undo the changes up to the state both have in common, then redo the
+    // ones down to `revision`
+    fn goto<F>(&mut self, revision: usize, cursor: Pos, mut replace: F) -> Option<Pos>
+    where
+        F: FnMut(usize, usize, Vec<String>) -> Vec<String>,
+    {
+        let target = self.nodes.get(revision)?;
+        let (target_cursor, parent) = (target.cursor, target.parent);
+        let down = self.ancestors(revision);
+        let up = self.ancestors(self.revision);
+        let common = down.iter().find(|revision| up.contains(revision)).copied().unwrap_or(0);
+        if let Some(node) = self.nodes.get_mut(self.revision) {
+            node.cursor = cursor;
+        }
+        for &revision in up.iter().take_while(|&&revision| revision != common) {
+            if let Some(node) = self.nodes.get_mut(revision) {
+                for edit in node.edits.iter_mut().rev() {
+                    swap(edit, &mut replace);
+                }
+            }
+        }
+        let path: Vec<usize> = down.into_iter().take_while(|&revision| revision != common).collect();
+        for &revision in path.iter().rev() {
+            if let Some(node) = self.nodes.get_mut(revision) {
+                for edit in &mut node.edits {
+                    swap(edit, &mut replace);
+                }
+            }
+        }
+        // redo from the parent comes back here
+        if revision > 0 {
+            if let Some(node) = self.nodes.get_mut(parent) {
+                node.next = Some(revision);
+            }
+        }
+        self.revision = revision;
+        self.pending = None;
+        Some(target_cursor)
+    }
+
+    // `revision`, its parent and so on up to the first state
+    fn ancestors(&self, revision: usize) -> Vec<usize> {
+        let mut ancestors = vec![revision];
+        let mut revision = revision;
+        while revision > 0 {
+            revision = match self.nodes.get(revision) {
+                Some(node) => node.parent,
+                None => break,
+            };
+            ancestors.push(revision);
+        }
+        ancestors
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_revision = self.revision;
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.revision != self.saved_revision
+    }
+
+    // the undo file, line based since rows never contain '\n':
+    //   rum-undo 2 <hash of the saved content>
+    //   <revision> <number of states>
+    // then for each state
+    //   <parent> <next, or the state itself for none> <cursor x> <cursor y>
+    //   <change cursor x> <change cursor y> <number of edits>
+    // and for each of its edits
+    //   <at> <len> <number of rows>
+    //   <rows>...
+    pub fn serialize(&self, content_hash: u64) -> String {
+        let mut text = format!("rum-undo 2 {:x}\n{} {}\n", content_hash, self.revision, self.nodes.len());
+        for (revision, node) in self.nodes.iter().enumerate() {
+            text.push_str(&format!(
+                "{} {} {} {} {} {} {}\n",
+                node.parent,
+                node.next.unwrap_or(revision),
+                node.cursor.x,
+                node.cursor.y,
+                node.change_cursor.x,
+                node.change_cursor.y,
+                node.edits.len()
+            ));
+            for edit in &node.edits {
+                text.push_str(&format!("{} {} {}\n", edit.at, edit.len, edit.rows.len()));
+                for row in &edit.rows {
+                    text.push_str(row);
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+
+    // None if the file is not an undo file for content hashed to `content_hash`
+    pub fn deserialize(text: &str, content_hash: u64) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()? != format!("rum-undo 2 {:x}", content_hash) {
+            return None;
+        }
+        let (revision, len) = match numbers(lines.next()?)?[..] {
+            [revision, len] => (revision, len),
+            _ => return None,
+        };
+        let mut nodes = Vec::new();
+        for index in 0..len {
+            let (parent, next, x, y, change_x, change_y, edit_count) = match numbers(lines.next()?)?[..] {
+                [parent, next, x, y, change_x, change_y, edit_count] => {
+                    (parent, next, x, y, change_x, change_y, edit_count)
+                }
+                _ => return None,
+            };
+            // a parent is an earlier state, going up always ends at the first
+            if parent >= cmp::max(index, 1) {
+                return None;
+            }
+            let mut edits = Vec::new();
+            for _ in 0..edit_count {
+                let (at, len, row_count) = match numbers(lines.next()?)?[..] {
+                    [at, len, row_count] => (at, len, row_count),
+                    _ => return None,
+                };
+                let mut rows = Vec::new();
+                for _ in 0..row_count {
+                    rows.push(lines.next()?.to_owned());
+                }
+                edits.push(Edit { at, len, rows });
+            }
+            nodes.push(Node {
+                parent,
+                edits,
+                cursor: Pos { x, y },
+                change_cursor: Pos { x: change_x, y: change_y },
+                next: Some(next).filter(|&next| next != index),
+            });
+        }
+        if revision >= nodes.len() {
+            return None;
+        }
+        Some(Self {
+            nodes,
+            revision,
+            saved_revision: revision,
+            pending: None,
+        })
+    }
+}
+
+fn swap<F>(edit: &mut Edit, replace: &mut F)
+where
+    F: FnMut(usize, usize, Vec<String>) -> Vec<String>,
+{
+    let rows = std::mem::take(&mut edit.rows);
+    let len = rows.len();
+    edit.rows = replace(edit.at, edit.len, rows);
+    edit.len = len;
+}
+
+fn numbers(line: &str) -> Option<Vec<usize>> {
+    line.split(' ').map(|number| number.parse().ok()).collect()
+}
+
+// FNV-1a, to tell whether a file changed since its undo file was written
+pub fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows and their history, as a document keeps them
+    struct Buffer {
+        rows: Vec<String>,
+        history: History,
+    }
+
+    impl Buffer {
+        fn new(rows: &[&str]) -> Self {
+            Self {
+                rows: rows.iter().map(|row| (*row).to_owned()).collect(),
+                history: History::default(),
+            }
+        }
+
+        // replace rows `at..at + len` by `rows`, as a change of its own
+        fn change(&mut self, at: usize, len: usize, rows: &[&str]) {
+            self.history.start_change(Pos::default());
+            let old = &self.rows;
+            self.history.record(at..at + len, rows.len(), || old[at..at + len].to_vec());
+            self.rows.splice(at..at + len, rows.iter().map(|row| (*row).to_owned()));
+        }
+
+        fn undo(&mut self) -> bool {
+            let rows = &mut self.rows;
+            self.history.undo(Pos::default(), |at, len, text| rows.splice(at..at + len, text).collect()).is_some()
+        }
+
+        fn redo(&mut self) -> bool {
+            let rows = &mut self.rows;
+            self.history.redo(Pos::default(), |at, len, text| rows.splice(at..at + len, text).collect()).is_some()
+        }
+
+        fn step_in_time(&mut self, forward: bool) -> bool {
+            let rows = &mut self.rows;
+            self.history
+                .step_in_time(forward, Pos::default(), |at, len, text| rows.splice(at..at + len, text).collect())
+                .is_some()
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_a_change() {
+        let mut buffer = Buffer::new(&["a", "b"]);
+        buffer.change(1, 1, &["c", "d"]);
+        assert_eq!(buffer.rows, ["a", "c", "d"]);
+        assert!(buffer.history.is_modified());
+        assert!(buffer.undo());
+        assert_eq!(buffer.rows, ["a", "b"]);
+        assert!(!buffer.history.is_modified());
+        assert!(!buffer.undo());
+        assert!(buffer.redo());
+        assert_eq!(buffer.rows, ["a", "c", "d"]);
+        assert!(!buffer.redo());
+    }
+
+    #[test]
+    fn a_change_after_undo_makes_a_branch() {
+        let mut buffer = Buffer::new(&["a"]);
+        buffer.change(0, 1, &["b"]);
+        assert!(buffer.undo());
+        buffer.change(0, 1, &["c"]);
+        assert!(buffer.undo());
+        assert_eq!(buffer.rows, ["a"]);
+        // redo goes down the branch made last
+        assert!(buffer.redo());
+        assert_eq!(buffer.rows, ["c"]);
+    }
+
+    #[test]
+    fn redo_goes_down_the_branch_last_visited() {
+        let mut buffer = Buffer::new(&["a"]);
+        buffer.change(0, 1, &["b"]);
+        buffer.change(1, 0, &["b2"]);
+        assert!(buffer.undo());
+        assert!(buffer.undo());
+        buffer.change(0, 1, &["c"]);
+        // back to the other branch through time, then up and down again
+        assert!(buffer.step_in_time(false));
+        assert_eq!(buffer.rows, ["b", "b2"]);
+        assert!(buffer.undo());
+        assert!(buffer.undo());
+        assert_eq!(buffer.rows, ["a"]);
+        assert!(buffer.redo());
+        assert!(buffer.redo());
+        assert_eq!(buffer.rows, ["b", "b2"]);
+    }
+
+    #[test]
+    fn step_in_time_across_branches() {
+        let mut buffer = Buffer::new(&["a"]);
+        buffer.change(0, 1, &["b"]);
+        buffer.change(1, 0, &["c"]);
+        assert!(buffer.undo());
+        assert!(buffer.undo());
+        buffer.change(0, 1, &["d"]);
+        let states = [vec!["a"], vec!["b"], vec!["b", "c"], vec!["d"]];
+        for state in states.iter().rev().skip(1) {
+            assert!(buffer.step_in_time(false));
+            assert_eq!(&buffer.rows, state);
+        }
+        assert!(!buffer.step_in_time(false));
+        for state in states.iter().skip(1) {
+            assert!(buffer.step_in_time(true));
+            assert_eq!(&buffer.rows, state);
+        }
+        assert!(!buffer.step_in_time(true));
+    }
+
+    #[test]
+    fn edits_within_a_change_keep_one_copy_of_a_row() {
+        let mut buffer = Buffer::new(&["a", "b"]);
+        buffer.history.start_change(Pos::default());
+        for text in ["ax", "axy"] {
+            let old = &buffer.rows;
+            buffer.history.record(0..1, 1, || old[0..1].to_vec());
+            buffer.rows[0] = text.to_owned();
+        }
+        assert_eq!(buffer.history.nodes.len(), 2);
+        assert_eq!(buffer.history.nodes[1].edits.len(), 1);
+        assert!(buffer.undo());
+        assert_eq!(buffer.rows, ["a", "b"]);
+    }
+
+    #[test]
+    fn serialize_and_deserialize() {
+        let mut buffer = Buffer::new(&["a"]);
+        buffer.change(0, 1, &["b"]);
+        assert!(buffer.undo());
+        buffer.change(0, 1, &["c", ""]);
+        let text = buffer.history.serialize(7);
+        assert!(History::deserialize(&text, 8).is_none());
+        buffer.history = History::deserialize(&text, 7).expect("an undo file");
+        assert!(!buffer.history.is_modified());
+        assert!(buffer.step_in_time(false));
+        assert_eq!(buffer.rows, ["b"]);
+        assert!(buffer.undo());
+        assert_eq!(buffer.rows, ["a"]);
+    }
+
+    #[test]
+    fn deserialize_refuses_a_parent_that_is_not_an_earlier_state() {
+        let states = |parents: [usize; 3]| {
+            let mut text = "rum-undo 2 7\n2 3\n".to_owned();
+            for parent in parents {
+                text.push_str(&format!("{} 0 0 0 0 0 0\n", parent));
+            }
+            History::deserialize(&text, 7)
+        };
+        assert!(states([0, 0, 1]).is_some());
+        // cycles would make going up to the first state loop forever
+        assert!(states([0, 2, 1]).is_none());
+        assert!(states([0, 1, 0]).is_none());
+        assert!(states([1, 0, 0]).is_none());
+        assert!(states([0, 0, 3]).is_none());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::NrFormats;
+use regex::Regex;
+use std::ops::Range;
+
+// `Ctrl-A` / `Ctrl-X`, add `amount` to the number at or after byte `at` of
+// `line`: a decimal, or as `formats` allow a hex number (`0x1f`), a date
+// (`2024-01-31`, by days) or the letter under the cursor. Returns the bytes
+// replaced and their new text, None when there's nothing to change
+pub fn increment(line: &str, at: usize, amount: i64, formats: NrFormats) -> Option<(Range<usize>, String)> {
+    let mut patterns = Vec::new();
+    if formats.date {
+        patterns.push(r"(?P<date>\d{4}-\d{2}-\d{2})");
+    }
+    if formats.hex {
+        patterns.push(r"(?P<hex>0[xX][[:xdigit:]]+)");
+    }
+    patterns.push(r"(?P<dec>-?\d+)");
+    let regex = Regex::new(&patterns.join("|")).ok()?;
+    let found = regex
+        .captures_iter(line)
+        .find(|caps| caps.get(0).map_or(false, |number| number.end() > at));
+    if formats.alpha {
+        let under = line.get(at..).and_then(|rest| rest.chars().next());
+        let on_number = found
+            .as_ref()
+            .and_then(|caps| caps.get(0))
+            .map_or(false, |number| number.start() <= at);
+        if let Some(c) = under.filter(char::is_ascii_alphabetic) {
+            if !on_number {
+                return Some((at..at.saturating_add(1), add_to_letter(c, amount).to_string()));
+            }
+        }
+    }
+    let caps = found?;
+    if let Some(date) = caps.name("date") {
+        return Some((date.range(), add_days(date.as_str(), amount)?));
+    }
+    if let Some(hex) = caps.name("hex") {
+        return Some((hex.range(), add_to_hex(hex.as_str(), amount)?));
+    }
+    let decimal = caps.name("dec")?;
+    let mut range = decimal.range();
+    // a `-` right after a word (`x-1`) is not a sign
+    let after_word = line
+        .get(..range.start)
+        .and_then(|before| before.chars().next_back())
+        .map_or(false, char::is_alphanumeric);
+    if after_word && decimal.as_str().starts_with('-') {
+        range.start = range.start.saturating_add(1);
+    }
+    let text = add_to_decimal(line.get(range.clone())?, amount)?;
+    Some((range, text))
+}
+
+// leading zeros keep the number of digits (`007` → `008`)
+fn add_to_decimal(text: &str, amount: i64) -> Option<String> {
+    let value = text.parse::<i64>().ok()?.checked_add(amount)?;
+    let digits = text.trim_start_matches('-');
+    let width = if digits.len() > 1 && digits.starts_with('0') {
+        digits.len()
+    } else {
+        0
+    };
+    let sign = if value < 0 { "-" } else { "" };
+    Some(format!("{}{:0width$}", sign, value.unsigned_abs(), width = width))
+}
+
+// the digits keep their number and case, the value wraps around
+fn add_to_hex(text: &str, amount: i64) -> Option<String> {
+    let (prefix, digits) = text.split_at(2);
+    let value = u64::from_str_radix(digits, 16).ok()?.wrapping_add_signed(amount);
+    let width = digits.len();
+    if digits.chars().any(|c| c.is_ascii_uppercase()) {
+        Some(format!("{}{:0width$X}", prefix, value, width = width))
+    } else {
+        Some(format!("{}{:0width$x}", prefix, value, width = width))
+    }
+}
+
+// stops at `a` / `z` (or `A` / `Z`)
+fn add_to_letter(c: char, amount: i64) -> char {
+    let (first, last) = if c.is_ascii_uppercase() { (b'A', b'Z') } else { (b'a', b'z') };
+    let letter = i64::from(c as u8).saturating_add(amount);
+    let letter = letter.clamp(i64::from(first), i64::from(last));
+    u8::try_from(letter).map_or(c, char::from)
+}
+
+// `amount` days later (earlier when negative), None for a date that doesn't
+// exist
+fn add_days(text: &str, amount: i64) -> Option<String> {
+    let mut parts = text.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(days_from_civil(year, month, day).checked_add(amount)?);
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year.checked_rem(4) == Some(0) && (year.checked_rem(100) != Some(0) || year.checked_rem(400) == Some(0)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar
+#[allow(clippy::integer_arithmetic, clippy::integer_division)]
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[allow(clippy::integer_arithmetic, clippy::integer_division)]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: NrFormats = NrFormats {
+        hex: true,
+        alpha: true,
+        date: true,
+    };
+
+    // `line` after adding `amount` at byte `at`
+    fn add(line: &str, at: usize, amount: i64, formats: NrFormats) -> Option<String> {
+        let (range, text) = increment(line, at, amount, formats)?;
+        let mut line = line.to_owned();
+        line.replace_range(range, &text);
+        Some(line)
+    }
+
+    #[test]
+    fn dates_roll_over_months_and_years() {
+        let date = |line: &str, amount: i64| add(line, 0, amount, ALL);
+        assert_eq!(date("2024-01-31", 1).as_deref(), Some("2024-02-01"));
+        assert_eq!(date("2024-02-01", -1).as_deref(), Some("2024-01-31"));
+        assert_eq!(date("2024-04-30", 1).as_deref(), Some("2024-05-01"));
+        assert_eq!(date("2024-12-31", 1).as_deref(), Some("2025-01-01"));
+        assert_eq!(date("2024-01-01", -1).as_deref(), Some("2023-12-31"));
+        assert_eq!(date("2024-01-31", 366).as_deref(), Some("2025-01-31"));
+    }
+
+    #[test]
+    fn dates_know_leap_years() {
+        let date = |line: &str, amount: i64| add(line, 0, amount, ALL);
+        assert_eq!(date("2024-02-28", 1).as_deref(), Some("2024-02-29"));
+        assert_eq!(date("2024-02-29", 1).as_deref(), Some("2024-03-01"));
+        assert_eq!(date("2023-02-28", 1).as_deref(), Some("2023-03-01"));
+        assert_eq!(date("2100-02-28", 1).as_deref(), Some("2100-03-01"));
+        assert_eq!(date("2000-02-28", 1).as_deref(), Some("2000-02-29"));
+        assert_eq!(date("2024-03-01", -1).as_deref(), Some("2024-02-29"));
+        // not a date, nothing changes
+        assert_eq!(date("2023-02-29", 1), None);
+        assert_eq!(date("2024-13-01", 1), None);
+    }
+
+    #[test]
+    fn dates_are_numbers_without_the_format() {
+        assert_eq!(add("2024-01-31", 0, 1, NrFormats::default()).as_deref(), Some("2025-01-31"));
+    }
+
+    #[test]
+    fn hex_keeps_width_and_case() {
+        assert_eq!(add("0x0f", 0, 1, ALL).as_deref(), Some("0x10"));
+        assert_eq!(add("0x00ff", 0, 1, ALL).as_deref(), Some("0x0100"));
+        assert_eq!(add("0xff", 0, 1, ALL).as_deref(), Some("0x100"));
+        assert_eq!(add("0x1F", 0, 1, ALL).as_deref(), Some("0x20"));
+        assert_eq!(add("0X0e", 0, 1, ALL).as_deref(), Some("0X0f"));
+        assert_eq!(add("0xaB", 0, -1, ALL).as_deref(), Some("0xAA"));
+        assert_eq!(add("0x0", 0, -1, ALL).as_deref(), Some("0xffffffffffffffff"));
+        // without the format, the 0 before the x is the number
+        assert_eq!(add("0x0f", 0, 1, NrFormats::default()).as_deref(), Some("1x0f"));
+    }
+
+    #[test]
+    fn minus_after_a_word_is_not_a_sign() {
+        let formats = NrFormats::default();
+        assert_eq!(add("x-1", 0, 1, formats).as_deref(), Some("x-2"));
+        assert_eq!(add("x-1", 0, -2, formats).as_deref(), Some("x--1"));
+        assert_eq!(add("x -1", 0, 1, formats).as_deref(), Some("x 0"));
+        assert_eq!(add("-1", 0, 2, formats).as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn decimals_keep_leading_zeros() {
+        let formats = NrFormats::default();
+        assert_eq!(add("007", 0, 1, formats).as_deref(), Some("008"));
+        assert_eq!(add("a 9 b", 0, 1, formats).as_deref(), Some("a 10 b"));
+        assert_eq!(add("1 2", 2, 5, formats).as_deref(), Some("1 7"));
+    }
+
+    #[test]
+    fn letters_stop_at_the_ends() {
+        assert_eq!(add("a", 0, 1, ALL).as_deref(), Some("b"));
+        assert_eq!(add("z", 0, 1, ALL).as_deref(), Some("z"));
+        assert_eq!(add("B", 0, -5, ALL).as_deref(), Some("A"));
+        assert_eq!(add("a", 0, 1, NrFormats::default()), None);
+    }
+}
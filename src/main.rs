@@ -13,12 +13,23 @@ mod row;
 mod document;
 mod filetype;
 mod highlighting;
+mod config;
+mod spell;
+mod history;
+mod gap_buffer;
+mod substitute;
+mod quickfix;
+mod tags;
+mod window;
+mod finder;
+mod increment;
 
 use editor::Editor;
 pub use editor::{Pos, SearchDirection};
-pub use terminal::Terminal;
-pub use row::Row;
+pub use terminal::{Input, Terminal};
+pub use row::{RenderOptions, Row};
 pub use filetype::{FileType, HighlightingOptions};
+pub use config::Config;
 
 fn main() {
     Editor::default().run();
@@ -0,0 +1,136 @@
+use regex::Regex;
+use std::fs;
+use std::process::{Command, Stdio};
+use unicode_segmentation::UnicodeSegmentation;
+
+// a location in the output of `:make`
+pub struct Entry {
+    pub filename: String,
+    pub line: usize, // 1 based
+    pub col: usize,  // 1 based, 0 if not given
+    pub message: String,
+}
+
+// the locations found by the last `:make`, `index` is the current one
+#[derive(Default)]
+pub struct Quickfix {
+    entries: Vec<Entry>,
+    index: usize,
+}
+
+impl Quickfix {
+    // run `cmd` through the shell and collect the locations in what it
+    // prints on stdout and stderr
+    pub fn make(cmd: &str) -> Result<Self, std::io::Error> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::null())
+            .output()?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok(Self {
+            entries: parse(&text),
+            index: 0,
+        })
+    }
+
+    // the lines of `files` matching `regex` (`:grep`), at the first match in
+    // each. Files that aren't text are skipped
+    pub fn grep(regex: &Regex, files: &[String]) -> Self {
+        let mut entries = Vec::new();
+        for filename in files {
+            let text = match fs::read(filename).ok().and_then(|bytes| String::from_utf8(bytes).ok()) {
+                Some(text) if !text.contains('\0') => text,
+                _ => continue,
+            };
+            for (index, line) in text.lines().enumerate() {
+                if let Some(found) = regex.find(line) {
+                    let col = line.get(..found.start()).unwrap_or("").graphemes(true).count();
+                    entries.push(Entry {
+                        filename: filename.clone(),
+                        line: index.saturating_add(1),
+                        col: col.saturating_add(1),
+                        message: line.trim().to_owned(),
+                    });
+                }
+            }
+        }
+        Self { entries, index: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the current entry and its index
+    pub fn current(&self) -> Option<(usize, &Entry)> {
+        self.entries.get(self.index).map(|entry| (self.index, entry))
+    }
+
+    // move to the next (or previous) entry, false at the end of the list
+    pub fn step(&mut self, forward: bool) -> bool {
+        let index = if forward {
+            self.index.saturating_add(1)
+        } else {
+            match self.index.checked_sub(1) {
+                Some(index) => index,
+                None => return false,
+            }
+        };
+        if index >= self.entries.len() {
+            return false;
+        }
+        self.index = index;
+        true
+    }
+}
+
+// `path:line:col: message` (or without the column) as printed by most
+// compilers, and rustc's `--> path:line:col` below its `error: message`
+fn parse(output: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut message = "";
+    for line in output.lines() {
+        let line = line.trim_start();
+        if let Some(location) = line.strip_prefix("--> ") {
+            if let Some((filename, line, col, _)) = split_location(location) {
+                entries.push(Entry {
+                    filename: filename.to_owned(),
+                    line,
+                    col,
+                    message: message.to_owned(),
+                });
+            }
+        } else if let Some((filename, line, col, text)) = split_location(line) {
+            entries.push(Entry {
+                filename: filename.to_owned(),
+                line,
+                col,
+                message: text.to_owned(),
+            });
+        } else if line.starts_with("error") || line.starts_with("warning") {
+            message = line;
+        }
+    }
+    entries
+}
+
+fn split_location(text: &str) -> Option<(&str, usize, usize, &str)> {
+    let mut parts = text.splitn(3, ':');
+    let filename = parts.next().filter(|name| !name.is_empty() && !name.contains(char::is_whitespace))?;
+    let line = parts.next()?.parse().ok()?;
+    let rest = parts.next().unwrap_or("");
+    let (col, message) = match rest.split_once(':') {
+        Some((col, message)) if col.parse::<usize>().is_ok() => (col.parse().ok()?, message),
+        _ => match rest.parse() {
+            Ok(col) => (col, ""),
+            Err(_) => (0, rest),
+        },
+    };
+    Some((filename, line, col, message.trim()))
+}
@@ -1,16 +1,45 @@
+use crate::config::ListChars;
 use crate::highlighting;
+use crate::spell::Dictionary;
 use crate::HighlightingOptions;
 use crate::SearchDirection;
+use regex::{Captures, Regex};
 use std::cmp;
+use std::ops::Range;
 use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
     pub is_highlighted: bool,
-    len: usize,
+    // whether the row starts/ends in a multiline comment, as of its last
+    // highlighting
+    starts_in_comment: bool,
+    ends_in_comment: bool,
+    // with `rainbow`, the brackets still open before/after the row as of its
+    // last highlighting, innermost last
+    brackets_before: Vec<char>,
+    brackets_after: Vec<char>,
+    // byte index of the start of each grapheme, so that the graphemes around
+    // the cursor or in the visible window are found without walking the
+    // whole row (rows can be very long, e.g. minified files)
+    boundaries: Vec<usize>,
+    // the boundaries from index `shift.0` on are off by `shift.1` bytes, the
+    // size change of the last edit. They are only fixed up as far as the next
+    // edit moves, so typing in a long row doesn't rewrite all of its tail
+    shift: (usize, isize),
+}
+
+// how a row is drawn on top of its syntax highlighting, indices are graphemes
+#[derive(Default)]
+pub struct RenderOptions {
+    pub selected: Option<Range<usize>>, // len() stands for the newline
+    pub background: Option<color::Rgb>, // background of the whole line
+    pub color_column: Option<usize>,    // column guide, a display column
+    pub listchars: Option<ListChars>,   // with `list`
 }
 
 impl From<&str> for Row {
@@ -19,25 +48,57 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlighting: Vec::new(),
             is_highlighted: false,
-            len: slice.graphemes(true).count(),
+            starts_in_comment: false,
+            ends_in_comment: false,
+            brackets_before: Vec::new(),
+            brackets_after: Vec::new(),
+            boundaries: slice.grapheme_indices(true).map(|(i, _)| i).collect(),
+            shift: (0, 0),
         }
     }
 }
 
 impl Row {
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
+    pub fn render(&self, start: usize, end: usize, opts: &RenderOptions) -> String {
+        let color_column = opts.color_column.map(|column| self.index_at_column(column));
+        let background_at = |index: usize| {
+            if opts.selected.as_ref().map_or(false, |r| r.contains(&index)) {
+                Some(highlighting::COLOR_SELECTION)
+            } else if color_column == Some(index) {
+                Some(highlighting::COLOR_COLORCOLUMN)
+            } else {
+                opts.background
+            }
+        };
+        // past the text only the newline, the column guide and the line
+        // background are drawn, as spaces
+        let fill_end = if opts.background.is_some() {
+            end
+        } else {
+            let newline_end = match &opts.selected {
+                Some(r) if r.contains(&self.len()) => self.len().saturating_add(1),
+                _ => 0,
+            };
+            let column_end = color_column.map_or(0, |column| column.saturating_add(1));
+            cmp::min(cmp::max(newline_end, column_end), end)
+        };
+        let eol = opts.listchars.and_then(|listchars| listchars.eol);
+        let fill_end = if eol.is_some() {
+            cmp::max(fill_end, cmp::min(self.len().saturating_add(1), end))
+        } else {
+            fill_end
+        };
+        // where the trailing spaces start
+        let trail_start = self
+            .len()
+            .saturating_sub(self.string.len().saturating_sub(self.string.trim_end_matches(' ').len()));
+        let end = cmp::min(end, self.len());
         let start = cmp::min(start, end);
         let mut parsed = String::new();
         let mut cur_highlighting = &highlighting::Type::None;
-        #[allow(clippy::integer_arithmetic)]
-        for (index, grapheme) in self.string[..]
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
+        let mut cur_background = None;
+        for (grapheme, index) in self.slice(start, end).graphemes(true).zip(start..) {
             if let Some(c) = grapheme.chars().next() {
                 let highlighting_type = self
                     .highlighting
@@ -51,79 +112,128 @@ impl Row {
                     parsed.push_str(&start_highlighting[..]);
                 }
 
-                parsed.push(c);
+                let background = background_at(index);
+                if background != cur_background {
+                    cur_background = background;
+                    parsed.push_str(&background_code(background));
+                }
+
+                let shown = match (c, opts.listchars) {
+                    ('\t', Some(ListChars { tab: Some(tab), .. })) => tab,
+                    ('\t', _) => ' ',
+                    (' ', Some(ListChars { trail: Some(trail), .. })) if index >= trail_start => trail,
+                    (' ', Some(ListChars { space: Some(space), .. })) => space,
+                    _ => visible_char(c),
+                };
+                parsed.push(shown);
             }
         }
+        for index in cmp::max(self.len(), start)..fill_end {
+            let background = background_at(index);
+            if background != cur_background {
+                cur_background = background;
+                parsed.push_str(&background_code(background));
+            }
+            match eol {
+                Some(eol) if index == self.len() => parsed.push(eol),
+                _ => parsed.push(' '),
+            }
+        }
+        if cur_background.is_some() {
+            parsed.push_str(&background_code(None));
+        }
         let end_highlight = format!("{}", termion::color::Fg(color::Reset));
         parsed.push_str(&end_highlight[..]);
         parsed
     }
 
     pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
-        } else {
-            let mut result: String = String::new();
-            let mut length = 0;
-            for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-                length += 1;
-                if index == at {
-                    length += 1;
-                    result.push(c);
-                }
-                result.push_str(grapheme);
-            }
-            self.len = length;
-            self.string = result;
-        }
+        let mut buf = [0; 4];
+        self.splice(at, at, c.encode_utf8(&mut buf));
     }
 
     pub fn delete(&mut self, at: usize) {
         if at >= self.len() {
             return;
         }
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index != at {
-                length += 1;
-                result.push_str(grapheme);
-            }
-        }
-        self.len = length;
-        self.string = result;
+        self.splice(at, at.saturating_add(1), "");
     }
 
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+        let len = self.len();
+        self.splice(len, len, &new.string);
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-        let mut row = String::new();
-        let mut length = 0;
-        let mut splitted_row = String::new();
-        let mut splitted_length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(grapheme);
+        let at = cmp::min(at, self.len());
+        let splitted_row = Self::from(&self.string[self.byte_index(at)..]);
+        self.string.truncate(self.byte_index(at));
+        self.boundaries.truncate(at);
+        self.is_highlighted = false;
+        splitted_row
+    }
+
+    // replace the graphemes from `start` up to (excluding) `end` by `text`,
+    // only the graphemes around the change are segmented again
+    fn splice(&mut self, start: usize, end: usize, text: &str) {
+        let len = self.len();
+        let end = cmp::min(end, len);
+        let start = cmp::min(start, end);
+        let (start_byte, end_byte) = (self.byte_index(start), self.byte_index(end));
+        // a change may merge or split the graphemes next to it
+        let from = start.saturating_sub(2);
+        let to = cmp::min(end.saturating_add(2), len);
+        let from_byte = self.byte_index(from);
+        let change = isize::try_from(text.len()).unwrap_or(isize::MAX).saturating_sub(
+            isize::try_from(end_byte.saturating_sub(start_byte)).unwrap_or(isize::MAX),
+        );
+        let to_byte = self.byte_index(to).saturating_add_signed(change);
+        // the boundaries before the change are made exact, the ones after it
+        // are left off by the old shift so they all share the new one
+        let (shift_from, shift) = self.shift;
+        for byte in self.boundaries.iter_mut().take(to).skip(shift_from) {
+            *byte = byte.saturating_add_signed(shift);
+        }
+        for byte in self.boundaries.iter_mut().take(shift_from).skip(to) {
+            *byte = byte.saturating_add_signed(shift.saturating_neg());
+        }
+        self.string.replace_range(start_byte..end_byte, text);
+        let around: Vec<usize> = self.string[from_byte..to_byte]
+            .grapheme_indices(true)
+            .map(|(i, _)| from_byte.saturating_add(i))
+            .collect();
+        let shift_from = from.saturating_add(around.len());
+        self.boundaries.splice(from..to, around);
+        self.shift = (shift_from, shift.saturating_add(change));
+    }
+
+    // byte index of the grapheme at `index`, the length of the row past it
+    pub fn byte_index(&self, index: usize) -> usize {
+        match self.boundaries.get(index) {
+            Some(&byte) if index >= self.shift.0 => byte.saturating_add_signed(self.shift.1),
+            Some(&byte) => byte,
+            None => self.string.len(),
+        }
+    }
+
+    // the graphemes from `start` up to (excluding) `end`
+    fn slice(&self, start: usize, end: usize) -> &str {
+        let end = cmp::max(start, end);
+        &self.string[self.byte_index(start)..self.byte_index(end)]
+    }
+
+    // grapheme index of the grapheme starting at byte `byte`
+    pub fn grapheme_index(&self, byte: usize) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low.saturating_add(high.saturating_sub(low).checked_div(2).unwrap_or(0));
+            if self.byte_index(middle) < byte {
+                low = middle.saturating_add(1);
             } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
+                high = middle;
             }
         }
-        self.string = row;
-        self.len = length;
-        self.is_highlighted = false;
-        Self {
-            string: splitted_row,
-            highlighting: Vec::new(),
-            is_highlighted: false,
-            len: splitted_length,
-        }
+        low
     }
 
     pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
@@ -137,50 +247,106 @@ impl Row {
             0
         };
         let end = if direction == SearchDirection::Forward {
-            self.len
+            self.len()
         } else {
             at
         };
 
-        #[allow(clippy::integer_arithmetic)]
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
+        let substring = self.slice(start, end);
         let matching_byte_index = if direction == SearchDirection::Forward {
             substring.find(query)
         } else {
             substring.rfind(query)
         };
 
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if byte_index == matching_byte_index {
-                    return Some(start + grapheme_index);
-                }
+        // a match that does not start on a grapheme boundary is not a match
+        let byte = self.byte_index(start).saturating_add(matching_byte_index?);
+        let index = self.grapheme_index(byte);
+        if index < self.len() && self.byte_index(index) == byte {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    // like `find` but `word` must not be part of a longer word
+    pub fn find_word(&self, word: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        let len = word.graphemes(true).count();
+        let mut at = at;
+        loop {
+            let index = self.find(word, at, direction)?;
+            let before = index.checked_sub(1).and_then(|i| self.char_at(i));
+            let after = self.char_at(index.saturating_add(len));
+            if before.map_or(true, is_sep) && after.map_or(true, is_sep) {
+                return Some(index);
             }
+            at = if direction == SearchDirection::Forward {
+                index.saturating_add(1)
+            } else {
+                index
+            };
+        }
+    }
+
+    // the first match of `regex` starting at or after grapheme `at`, with
+    // the graphemes it covers
+    pub fn find_regex<'a>(&'a self, regex: &Regex, at: usize) -> Option<(Range<usize>, Captures<'a>)> {
+        if at > self.len() {
+            return None;
         }
-        None
+        let captures = regex.captures_at(&self.string, self.byte_index(at))?;
+        let found = captures.get(0)?;
+        let range = self.grapheme_index(found.start())..self.grapheme_index(found.end());
+        Some((range, captures))
     }
 
-    fn highlight_match(&mut self, word: &Option<String>) {
+    // the start of the last match of `regex` that ends before grapheme `at`
+    pub fn rfind_regex(&self, regex: &Regex, at: usize) -> Option<usize> {
+        let found = regex.find_iter(self.slice(0, at)).last()?;
+        Some(self.grapheme_index(found.start()))
+    }
+
+    fn highlight_match(&mut self, word: &Option<Regex>) {
         if let Some(word) = word {
-            if word.is_empty() {
-                return;
+            for found in word.find_iter(&self.string) {
+                let start = self.grapheme_index(found.start());
+                let end = self.grapheme_index(found.end());
+                for hl_type in self.highlighting.iter_mut().take(end).skip(start) {
+                    *hl_type = highlighting::Type::Match;
+                }
             }
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    for i in index.saturating_add(search_match)..next_index {
-                        self.highlighting[i] = highlighting::Type::Match;
-                    }
-                    index = next_index;
-                } else {
-                    break;
+        }
+    }
+
+    // flag the unknown words, only prose (comments and strings) is checked
+    fn highlight_spelling(&mut self, chars: &[char], dictionary: &Dictionary) {
+        let is_prose = |hl_type: Option<&highlighting::Type>| {
+            matches!(
+                hl_type,
+                Some(
+                    highlighting::Type::Comment
+                        | highlighting::Type::MultilineComment
+                        | highlighting::Type::String
+                )
+            )
+        };
+        let mut index = 0;
+        while index < chars.len() {
+            if !chars[index].is_alphabetic() {
+                index = index.saturating_add(1);
+                continue;
+            }
+            let start = index;
+            while chars.get(index).map_or(false, |c| c.is_alphabetic()) {
+                index = index.saturating_add(1);
+            }
+            let word: String = chars[start..index].iter().collect();
+            if word.chars().count() > 1
+                && (start..index).all(|i| is_prose(self.highlighting.get(i)))
+                && !dictionary.check(&word)
+            {
+                for hl_type in &mut self.highlighting[start..index] {
+                    *hl_type = highlighting::Type::Misspelled;
                 }
             }
         }
@@ -235,6 +401,37 @@ impl Row {
         false
     }
 
+    // `rainbow`, brackets outside of strings and comments are colored by
+    // their nesting depth, carried over from the rows above in `brackets`. A
+    // closing bracket that does not match the innermost open one is an error
+    fn highlight_brackets(&mut self, chars: &[char], brackets: &mut Vec<char>) {
+        for (c, highlighting) in chars.iter().zip(self.highlighting.iter_mut()) {
+            if *highlighting != highlighting::Type::None {
+                continue;
+            }
+            match c {
+                '(' | '[' | '{' => {
+                    *highlighting = highlighting::Type::Bracket(brackets.len());
+                    brackets.push(*c);
+                }
+                ')' | ']' | '}' => {
+                    let open = match c {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    if brackets.last() == Some(&open) {
+                        brackets.pop();
+                        *highlighting = highlighting::Type::Bracket(brackets.len());
+                    } else {
+                        *highlighting = highlighting::Type::UnmatchedBracket;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
     fn highlight_multiline_comment(
         &mut self,
         index: &mut usize,
@@ -245,12 +442,15 @@ impl Row {
         if opts.multiline_comments() && c == '/' && *index < chars.len() {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
                 if *next_char == '*' {
-                    let closing_index =
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
-                            *index + closing_index + 4 // 4 = len(/**/)
-                        } else {
-                            chars.len()
-                        };
+                    // an unclosed comment goes on in the next rows
+                    let closing_index = if let Some(closing_index) =
+                        find_comment_end(chars, index.saturating_add(2))
+                    {
+                        closing_index
+                    } else {
+                        self.ends_in_comment = true;
+                        chars.len()
+                    };
                     for _ in *index..closing_index {
                         self.highlighting.push(highlighting::Type::MultilineComment);
                         *index += 1;
@@ -309,6 +509,8 @@ impl Row {
                     if *next_char != '.' && !next_char.is_ascii_digit() {
                         break;
                     }
+                } else {
+                    break; // the number ends the row
                 }
             }
             return true;
@@ -445,35 +647,31 @@ impl Row {
     pub fn highlight(
         &mut self,
         opts: &HighlightingOptions,
-        word: &Option<String>,   // word: for searching highlight
+        word: &Option<Regex>,    // word: for searching highlight
+        spell: Option<&Dictionary>, // check spelling in comments and strings
         start_with_comment: bool,
+        brackets: Option<&mut Vec<char>>, // open brackets, with `rainbow`
     ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        let mut index = 0; // byte index in chars
-
-        // If the row is already highlighted(indicated by self.is_highlighted),
-        // and we are not searching, we simply just stop highlighting it
-        // but if the line is in a multiline comment, we still need to give
-        // the correct return value
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                {
-                    let graphemes: String = self.string.graphemes(true).collect();
-                    if graphemes.len() > 1 && (graphemes[graphemes.len() - 2..] == *"*/") {
-                        return true;
-                    }
-                }
+        // If the row is already highlighted(indicated by self.is_highlighted)
+        // in the same multiline comment state, and we are not searching, we
+        // simply just stop highlighting it and give the state it ended in
+        let same_brackets = brackets.as_ref().map_or(true, |brackets| **brackets == self.brackets_before);
+        if self.is_highlighted && word.is_none() && self.starts_in_comment == start_with_comment && same_brackets {
+            if let Some(brackets) = brackets {
+                brackets.clone_from(&self.brackets_after);
             }
-            return false;
+            return self.ends_in_comment;
         }
 
+        let chars: Vec<char> = self.string.chars().collect();
+        let mut index = 0; // byte index in chars
         self.highlighting = Vec::new();
-        let mut in_multi_comment = start_with_comment;
-        if in_multi_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index + 2
+        self.ends_in_comment = false;
+        if start_with_comment {
+            let closing_index = if let Some(closing_index) = find_comment_end(&chars, 0) {
+                closing_index
             } else {
+                self.ends_in_comment = true;
                 chars.len()
             };
             for _ in 0..closing_index {
@@ -484,10 +682,8 @@ impl Row {
 
         while let Some(c) = chars.get(index) {
             if self.highlight_multiline_comment(&mut index, opts, *c, &chars) {
-                in_multi_comment = true;
                 continue;
             }
-            in_multi_comment = false;
             if self.highlight_char(&mut index, opts, *c, &chars)
                 || self.highlight_fn(&mut index, opts, *c, &chars)
                 || self.highlight_comment(&mut index, opts, *c, &chars)
@@ -501,13 +697,212 @@ impl Row {
             self.highlighting.push(highlighting::Type::None);
             index += 1;
         }
+        if let Some(brackets) = brackets {
+            self.brackets_before.clone_from(brackets);
+            self.highlight_brackets(&chars, brackets);
+            self.brackets_after.clone_from(brackets);
+        }
 
-        self.highlight_match(word);
-        if in_multi_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
-            return true; // we are still in the multiline comment
+        if let Some(dictionary) = spell {
+            self.highlight_spelling(&chars, dictionary);
         }
+        self.highlight_match(word);
         self.is_highlighted = true;
-        false // we are out of the multiline comment
+        self.starts_in_comment = start_with_comment;
+        self.ends_in_comment // whether we are still in the multiline comment
+    }
+
+    // whether the row ended in a multiline comment when last highlighted
+    pub fn ends_in_comment(&self) -> bool {
+        self.ends_in_comment
+    }
+
+    // with `rainbow`, the brackets still open after the row when last
+    // highlighted
+    pub fn brackets_after(&self) -> &[char] {
+        &self.brackets_after
+    }
+
+    #[cfg(test)]
+    pub fn highlighting(&self) -> &[highlighting::Type] {
+        &self.highlighting
+    }
+
+    // the run of blanks to break the row at so that the text before it fits
+    // in `width` display columns, the indentation is never a break point. A
+    // first word wider than `width` is not split, the row breaks right after
+    // it (as Vim does)
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn wrap_range(&self, width: usize) -> Option<Range<usize>> {
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        let is_blank = |grapheme: &str| grapheme.chars().all(char::is_whitespace);
+        let indent = graphemes.iter().take_while(|g| is_blank(g)).count();
+        let mut columns = 0;
+        let mut start = None;
+        for (index, grapheme) in graphemes.iter().enumerate() {
+            if index > indent && is_blank(grapheme) && !is_blank(graphemes[index - 1]) {
+                let fits = columns <= width;
+                if fits || start.is_none() {
+                    start = Some(index);
+                }
+                if !fits {
+                    break;
+                }
+            }
+            columns += grapheme.width();
+        }
+        let start = start?;
+        let end = start + graphemes[start..].iter().take_while(|g| is_blank(g)).count();
+        Some(start..end)
+    }
+
+    // the word (delimited by separators) around the grapheme at `index`
+    pub fn word_at(&self, index: usize) -> Option<String> {
+        self.token_at(index, |c| !is_sep(c))
+    }
+
+    // the file path around `index`, made of the characters of Vim's default
+    // 'isfname'
+    pub fn path_at(&self, index: usize) -> Option<String> {
+        self.token_at(index, |c| c.is_alphanumeric() || "/.-_+,#$%~=".contains(c))
+    }
+
+    // `iw` / `aw`, the graphemes of the word, the run of other separators or
+    // the whitespace at `index`. `aw` adds the whitespace after a word (or
+    // before it when there is none after), or the word after whitespace
+    pub fn word_object(&self, index: usize, around: bool) -> Option<Range<usize>> {
+        let class = |index: usize| {
+            let c = self.grapheme_at(index)?.chars().next()?;
+            Some(if c.is_whitespace() {
+                0
+            } else if is_sep(c) {
+                1
+            } else {
+                2
+            })
+        };
+        let run_start = |index: usize| {
+            let mut start = index;
+            while start > 0 && class(start.saturating_sub(1)) == class(index) {
+                start = start.saturating_sub(1);
+            }
+            start
+        };
+        let run_end = |index: usize| {
+            let mut end = index;
+            while class(end).is_some() && class(end) == class(index) {
+                end = end.saturating_add(1);
+            }
+            end
+        };
+        let on_space = class(index)? == 0;
+        let (start, end) = (run_start(index), run_end(index));
+        if !around {
+            return Some(start..end);
+        }
+        if on_space {
+            return Some(start..if class(end).is_some() { run_end(end) } else { end });
+        }
+        if class(end) == Some(0) {
+            Some(start..run_end(end))
+        } else if start > 0 && class(start.saturating_sub(1)) == Some(0) {
+            Some(run_start(start.saturating_sub(1))..end)
+        } else {
+            Some(start..end)
+        }
+    }
+
+    // the longest run of graphemes around `index` whose characters all
+    // satisfy `is_part`
+    fn token_at<F: Fn(char) -> bool>(&self, index: usize, is_part: F) -> Option<String> {
+        let is_part = |index: usize| self.grapheme_at(index).map_or(false, |g| g.chars().all(&is_part));
+        if !is_part(index) {
+            return None;
+        }
+        let mut start = index;
+        while start > 0 && is_part(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = index;
+        while is_part(end) {
+            end = end.saturating_add(1);
+        }
+        Some(self.substring(start, end))
+    }
+
+    // the graphemes from `start` up to (excluding) `end`
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        let end = cmp::min(end, self.len());
+        self.slice(cmp::min(start, end), end).to_owned()
+    }
+
+    pub fn grapheme_at(&self, index: usize) -> Option<&str> {
+        if index < self.len() {
+            Some(self.slice(index, index.saturating_add(1)))
+        } else {
+            None
+        }
+    }
+
+    // first char of the grapheme at `index`
+    pub fn char_at(&self, index: usize) -> Option<char> {
+        self.grapheme_at(index).and_then(|grapheme| grapheme.chars().next())
+    }
+
+    // start of the word that ends right before the grapheme at `index`
+    pub fn word_start_before(&self, index: usize) -> usize {
+        let mut start = cmp::min(index, self.len());
+        while start > 0
+            && self
+                .grapheme_at(start.saturating_sub(1))
+                .map_or(false, |g| !g.chars().any(is_sep))
+        {
+            start = start.saturating_sub(1);
+        }
+        start
+    }
+
+    // where `Ctrl-W` deletes back to from `index`: over the whitespace
+    // before it, then the word (or run of punctuation) before that
+    pub fn delete_word_start(&self, index: usize) -> usize {
+        let is_space = |grapheme: &str| grapheme.chars().all(char::is_whitespace);
+        let is_word = |grapheme: &str| grapheme.chars().all(|c| !is_sep(c));
+        let before = |start: usize| self.grapheme_at(start.saturating_sub(1)).unwrap_or("");
+        let mut start = cmp::min(index, self.len());
+        while start > 0 && is_space(before(start)) {
+            start = start.saturating_sub(1);
+        }
+        if start > 0 {
+            let word = is_word(before(start));
+            while start > 0 && !is_space(before(start)) && is_word(before(start)) == word {
+                start = start.saturating_sub(1);
+            }
+        }
+        start
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.string.split(is_sep).filter(|word| !word.is_empty())
+    }
+
+    // display width
+    pub fn width(&self) -> usize {
+        self.string.width()
+    }
+
+    // the index of the grapheme drawn over display column `column` (e.g. the
+    // second column of a wide character is the character), past the text
+    // each column is an index
+    fn index_at_column(&self, column: usize) -> usize {
+        let mut columns: usize = 0;
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            // a control character is drawn as a symbol
+            columns = columns.saturating_add(cmp::max(grapheme.width(), 1));
+            if columns > column {
+                return index;
+            }
+        }
+        self.len().saturating_add(column.saturating_sub(columns))
     }
 
     pub fn index_first_char(&self) -> usize {
@@ -521,12 +916,25 @@ impl Row {
         index
     }
 
+    // the number of leading spaces, all of them for a row of spaces
+    pub fn indent(&self) -> usize {
+        self.string.len().saturating_sub(self.string.trim_start_matches(' ').len())
+    }
+
     pub fn len(&self) -> usize {
-        self.len
+        self.boundaries.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.len == 0
+        self.boundaries.is_empty()
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.string.chars().all(char::is_whitespace)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -534,6 +942,33 @@ impl Row {
     }
 }
 
+fn background_code(background: Option<color::Rgb>) -> String {
+    if let Some(background) = background {
+        format!("{}", color::Bg(background))
+    } else {
+        format!("{}", color::Bg(color::Reset))
+    }
+}
+
+// control characters would move the terminal's cursor, they are drawn as
+// their symbol from the Control Pictures block (a tab is drawn as a space)
+fn visible_char(c: char) -> char {
+    match c {
+        '\0'..='\x1f' => char::from_u32(u32::from(c).saturating_add(0x2400)).unwrap_or(c),
+        '\x7f' => '\u{2421}',
+        c => c,
+    }
+}
+
+// the char index right after the first "*/" from `from`
+fn find_comment_end(chars: &[char], from: usize) -> Option<usize> {
+    chars
+        .get(from..)?
+        .windows(2)
+        .position(|pair| pair == ['*', '/'])
+        .map(|i| from.saturating_add(i).saturating_add(2))
+}
+
 fn is_sep(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
@@ -545,3 +980,144 @@ fn is_func_start(c: char) -> bool {
 fn is_func_char(c: char) -> bool {
     c.is_alphabetic() || c == '_' || c.is_numeric()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileType;
+    use highlighting::Type;
+
+    fn highlight(text: &str) -> (Row, bool) {
+        let mut row = Row::from(text);
+        let ends_in_comment = row.highlight(FileType::from("test.rs").options(), &None, None, false, None);
+        (row, ends_in_comment)
+    }
+
+    fn is_comment(row: &Row, range: Range<usize>) -> bool {
+        row.highlighting()
+            .get(range)
+            .map_or(false, |types| types.iter().all(|hl| *hl == Type::MultilineComment))
+    }
+
+    #[test]
+    fn comment_closed_before_code() {
+        let (row, ends_in_comment) = highlight("/* x */ code");
+        assert!(!ends_in_comment);
+        assert!(is_comment(&row, 0..7));
+        assert!(!row.highlighting().iter().skip(7).any(|hl| *hl == Type::MultilineComment));
+    }
+
+    #[test]
+    fn comment_after_accented_character() {
+        let (row, ends_in_comment) = highlight("é/* x */ y");
+        assert!(!ends_in_comment);
+        assert!(row.highlighting().first() != Some(&Type::MultilineComment));
+        assert!(is_comment(&row, 1..8));
+        assert!(!row.highlighting().iter().skip(8).any(|hl| *hl == Type::MultilineComment));
+    }
+
+    #[test]
+    fn edits_keep_grapheme_boundaries() {
+        let mut row = Row::from("aé\u{301}b c日本");
+        // a char to insert, or None to delete
+        let edits = [
+            (3, Some('x')),
+            (0, Some('\u{301}')),
+            (6, None),
+            (2, Some('日')),
+            (1, None),
+            (8, Some('z')),
+            (4, Some('\u{301}')),
+            (0, None),
+        ];
+        for (at, c) in edits {
+            match c {
+                Some(c) => row.insert(at, c),
+                None => row.delete(at),
+            }
+            let fresh = Row::from(row.as_str());
+            assert_eq!(row.len(), fresh.len());
+            for index in 0..=row.len() {
+                assert_eq!(row.byte_index(index), fresh.byte_index(index));
+            }
+            for byte in 0..=row.as_str().len() {
+                assert_eq!(row.grapheme_index(byte), fresh.grapheme_index(byte));
+            }
+        }
+    }
+
+    #[test]
+    fn wrap_at_the_last_blank_that_fits() {
+        let row = Row::from("  one two  three");
+        assert_eq!(row.wrap_range(10), Some(9..11));
+        assert_eq!(row.wrap_range(7), Some(5..6));
+        // the indentation is not a break point
+        assert_eq!(Row::from("   one").wrap_range(2), None);
+    }
+
+    #[test]
+    fn wrap_after_a_first_word_wider_than_the_width() {
+        assert_eq!(Row::from("abcdefgh ij kl").wrap_range(4), Some(8..9));
+        assert_eq!(Row::from("abcdefgh").wrap_range(4), None);
+    }
+
+    // the character drawn on the column guide at `column`
+    fn on_guide(text: &str, column: usize) -> Option<char> {
+        let opts = RenderOptions {
+            color_column: Some(column),
+            ..RenderOptions::default()
+        };
+        let rendered = Row::from(text).render(0, 80, &opts);
+        let guide = background_code(Some(highlighting::COLOR_COLORCOLUMN));
+        rendered.split_once(&guide)?.1.chars().next()
+    }
+
+    #[test]
+    fn color_column_is_a_display_column() {
+        assert_eq!(on_guide("abc", 1), Some('b'));
+        // wide characters take two columns each
+        assert_eq!(on_guide("日本x", 1), Some('日'));
+        assert_eq!(on_guide("日本x", 2), Some('本'));
+        assert_eq!(on_guide("日本x", 4), Some('x'));
+        assert_eq!(on_guide("日本x", 6), Some(' '));
+        assert_eq!(Row::from("日本x").index_at_column(6), 4);
+    }
+
+    #[test]
+    fn tab_is_blank_unless_listed() {
+        let row = Row::from("a\tb");
+        assert!(row.render(0, 80, &RenderOptions::default()).contains("a b"));
+        let listchars = ListChars {
+            tab: Some('>'),
+            ..ListChars::default()
+        };
+        let opts = RenderOptions {
+            listchars: Some(listchars),
+            ..RenderOptions::default()
+        };
+        assert!(row.render(0, 80, &opts).contains("a>b"));
+    }
+
+    // cargo test --release -- --ignored --nocapture long_row
+    #[test]
+    #[ignore]
+    fn long_row_benchmark() {
+        let mut row = Row::from("é".repeat(500_000).as_str());
+        let started = std::time::Instant::now();
+        for _ in 0..1000 {
+            std::hint::black_box(row.render(250_000, 250_100, &RenderOptions::default()));
+        }
+        println!("render 100 of 500k columns: {:?}", started.elapsed() / 1000);
+        let started = std::time::Instant::now();
+        for i in 0..1000 {
+            row.insert(250_000 + i, 'x');
+        }
+        println!("insert into a 500k grapheme row: {:?}", started.elapsed() / 1000);
+        let started = std::time::Instant::now();
+        for _ in 0..1000 {
+            row.delete(250_000);
+        }
+        println!("delete from a 500k grapheme row: {:?}", started.elapsed() / 1000);
+        assert_eq!(row.len(), 500_000);
+    }
+}
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use std::fs;
+
+const SYSTEM_WORDS: &str = "/usr/share/dict/words";
+
+// used when there is no system word list, so that at least the most common
+// words are not flagged
+const STUB_WORDS: &[&str] = &[
+    "a", "about", "above", "after", "again", "all", "also", "an", "and", "any", "are", "as",
+    "at", "be", "because", "been", "before", "being", "below", "between", "both", "but", "by",
+    "can", "could", "did", "do", "does", "doing", "down", "during", "each", "else", "few",
+    "first", "for", "from", "further", "get", "had", "has", "have", "having", "he", "her",
+    "here", "him", "his", "how", "i", "if", "in", "into", "is", "it", "its", "just", "last",
+    "like", "line", "make", "may", "me", "might", "more", "most", "must", "my", "need", "new",
+    "no", "nor", "not", "now", "of", "off", "on", "once", "one", "only", "or", "other", "our",
+    "out", "over", "own", "same", "see", "she", "should", "so", "some", "still", "such",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "those",
+    "through", "to", "too", "two", "under", "until", "up", "use", "used", "very", "was", "we",
+    "were", "what", "when", "where", "which", "while", "who", "why", "will", "with", "would",
+    "you", "your",
+];
+
+// the words accepted by the spell checker, compared case-insensitively
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    pub fn load() -> Self {
+        let words = if let Ok(contents) = fs::read_to_string(SYSTEM_WORDS) {
+            contents.lines().map(str::to_lowercase).collect()
+        } else {
+            STUB_WORDS.iter().map(|word| (*word).to_owned()).collect()
+        };
+        Self { words }
+    }
+
+    pub fn check(&self, word: &str) -> bool {
+        self.words.contains(&word.to_lowercase())
+    }
+
+    // `zg`, accept the word from now on
+    pub fn add(&mut self, word: &str) {
+        self.words.insert(word.to_lowercase());
+    }
+}
@@ -0,0 +1,218 @@
+use regex::{Captures, Regex, RegexBuilder};
+
+// `:[%]s/pattern/replacement/[flags]`, the separator is the character
+// after the `s` and can be escaped with a backslash in the pattern and the
+// replacement
+#[derive(Clone)]
+pub struct Substitute {
+    pub whole_file: bool, // `%`, otherwise only the cursor row
+    pub pattern: String,
+    pub replacement: String,
+    pub global: bool, // `g`, every match in a row instead of the first
+    pub confirm: bool, // `c`, ask before replacing each match
+}
+
+impl Substitute {
+    // None if `cmd` is not a substitution, the parts that are not typed yet
+    // are empty so that a command still being typed can be previewed
+    pub fn parse(cmd: &str) -> Option<Self> {
+        let (whole_file, cmd) = match cmd.strip_prefix('%') {
+            Some(cmd) => (true, cmd),
+            None => (false, cmd),
+        };
+        let cmd = cmd
+            .strip_prefix("substitute")
+            .or_else(|| cmd.strip_prefix("s"))?;
+        let mut chars = cmd.chars();
+        let separator = chars.next()?;
+        if separator.is_alphanumeric() || separator.is_whitespace() || separator == '\\' {
+            return None;
+        }
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            if escaped {
+                if c != separator {
+                    parts.last_mut()?.push('\\');
+                }
+                parts.last_mut()?.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == separator && parts.len() < 3 {
+                parts.push(String::new());
+            } else {
+                parts.last_mut()?.push(c);
+            }
+        }
+        if escaped {
+            parts.last_mut()?.push('\\');
+        }
+        let mut parts = parts.into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+        let flags = parts.next().unwrap_or_default();
+        Some(Self {
+            whole_file,
+            pattern,
+            replacement,
+            global: flags.contains('g'),
+            confirm: flags.contains('c'),
+        })
+    }
+
+    pub fn regex(&self, ignore_case: bool) -> Result<Regex, String> {
+        RegexBuilder::new(&translate(&self.pattern))
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|_| format!("Invalid pattern: {}", self.pattern))
+    }
+
+    // the replacement for a match: `&` and `\0` are the whole match, `\1`..`\9`
+    // its groups. `\U` / `\L` turn what follows to upper / lower case until
+    // `\E` (or `\e`), `\u` / `\l` only the next character. `\n` and `\r`
+    // break the row, `\t` is a tab and any other escaped character is
+    // itself (`\\`, `\&`)
+    pub fn expand(&self, captures: &Captures) -> String {
+        let mut text = String::new();
+        let mut case = Case::Keep;
+        let mut next_case = Case::Keep;
+        let mut chars = self.replacement.chars();
+        while let Some(c) = chars.next() {
+            let piece = match c {
+                '&' => captures.get(0).map_or("", |group| group.as_str()).to_owned(),
+                '\\' => match chars.next() {
+                    Some(digit @ '0'..='9') => digit
+                        .to_digit(10)
+                        .and_then(|group| captures.get(group as usize))
+                        .map_or("", |group| group.as_str())
+                        .to_owned(),
+                    Some('U') => {
+                        case = Case::Upper;
+                        continue;
+                    }
+                    Some('L') => {
+                        case = Case::Lower;
+                        continue;
+                    }
+                    Some('E' | 'e') => {
+                        case = Case::Keep;
+                        continue;
+                    }
+                    Some('u') => {
+                        next_case = Case::Upper;
+                        continue;
+                    }
+                    Some('l') => {
+                        next_case = Case::Lower;
+                        continue;
+                    }
+                    Some('n' | 'r') => String::from('\n'),
+                    Some('t') => String::from('\t'),
+                    Some(c) => String::from(c),
+                    None => String::from('\\'),
+                },
+                c => String::from(c),
+            };
+            let mut piece = piece.chars();
+            if let Some(first) = piece.next() {
+                text.push_str(&next_case.apply(&String::from(first), case));
+                text.push_str(&case.apply(piece.as_str(), Case::Keep));
+                next_case = Case::Keep;
+            }
+        }
+        text
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Case {
+    Keep,
+    Upper,
+    Lower,
+}
+
+impl Case {
+    // `text` in this case, or in `otherwise` when this is Keep
+    fn apply(self, text: &str, otherwise: Self) -> String {
+        match (self, otherwise) {
+            (Self::Upper, _) | (Self::Keep, Self::Upper) => text.to_uppercase(),
+            (Self::Lower, _) | (Self::Keep, Self::Lower) => text.to_lowercase(),
+            (Self::Keep, Self::Keep) => text.to_owned(),
+        }
+    }
+}
+
+// a Vim pattern in the syntax of the regex crate: `\(`, `\)`, `\|`, `\+`,
+// `\?` (or `\=`) and `\{n,m}` are operators while the same characters
+// without the backslash are literal, `\<` and `\>` are word boundaries
+fn translate(pattern: &str) -> String {
+    let mut regex = String::new();
+    let mut in_braces = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '|' | '+' | '?')) => regex.push(c),
+                Some('=') => regex.push('?'),
+                Some('{') => {
+                    in_braces = true;
+                    regex.push('{');
+                }
+                Some('}') if in_braces => {
+                    in_braces = false;
+                    regex.push('}');
+                }
+                Some('<' | '>') => regex.push_str("\\b"),
+                // classes like `\w` and `\s`, and `\n` / `\t`
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    regex.push('\\');
+                    regex.push(c);
+                }
+                Some(c) => regex.push_str(&regex::escape(&String::from(c))),
+                None => regex.push_str("\\\\"),
+            },
+            '}' if in_braces => {
+                in_braces = false;
+                regex.push('}');
+            }
+            '(' | ')' | '|' | '+' | '?' | '{' | '}' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `:s/pattern/replacement/` applied to the first match in `text`
+    fn substitute(cmd: &str, text: &str) -> String {
+        let substitute = Substitute::parse(cmd).expect("a substitution");
+        let regex = substitute.regex(false).expect("a valid pattern");
+        regex
+            .replacen(text, 1, |captures: &Captures| substitute.expand(captures))
+            .into_owned()
+    }
+
+    #[test]
+    fn reuses_captures() {
+        assert_eq!(substitute(r"s/\(\w\+\) \(\w\+\)/\2 \1/", "hello world"), "world hello");
+        assert_eq!(substitute(r"s/\(\w\+\) \(\w\+\)/\2 \1 \1/", "hello world"), "world hello hello");
+        assert_eq!(substitute("s/o/[&&]/", "foo"), "f[oo]o");
+        assert_eq!(substitute(r"s/o/\0\&/", "foo"), "fo&o");
+    }
+
+    #[test]
+    fn changes_case() {
+        assert_eq!(substitute(r"s/\w\+/\U&/", "hello world"), "HELLO world");
+        assert_eq!(substitute(r"s/.*/\L&/", "Hello World"), "hello world");
+        assert_eq!(substitute(r"s/\(\w\+\) \(\w\+\)/\U\1\E \2/", "hello world"), "HELLO world");
+        assert_eq!(substitute(r"s/\w\+/\u&/", "hello"), "Hello");
+        assert_eq!(substitute(r"s/\w\+/\L\u&/", "HELLO"), "Hello");
+    }
+}
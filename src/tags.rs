@@ -0,0 +1,70 @@
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+// where a tag is defined, the file is relative to the tags file's directory
+// in the tags file and resolved when read
+pub struct Tag {
+    pub filename: String,
+    pub address: Address,
+}
+
+pub enum Address {
+    Line(usize), // 1 based
+    Pattern(Regex),
+}
+
+// look `name` up in the ctags file `path` (`name<Tab>file<Tab>address`,
+// the address being a line number or a `/pattern/`), None if it has no such
+// tag
+pub fn find(path: &Path, name: &str) -> Result<Option<Tag>, std::io::Error> {
+    let text = fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(text
+        .lines()
+        .filter(|line| !line.starts_with("!_TAG_"))
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            if fields.next()? != name {
+                return None;
+            }
+            let filename = fields.next()?;
+            // the address may be followed by `;"` and extension fields
+            let address = fields.next()?;
+            let address = address.split(";\"\t").next().unwrap_or(address);
+            let address = address.strip_suffix(";\"").unwrap_or(address);
+            Some(Tag {
+                filename: dir.join(filename).to_string_lossy().into_owned(),
+                address: parse_address(address)?,
+            })
+        })
+        .next())
+}
+
+// a search pattern in a tags file is literal except for a leading `^` and
+// a trailing `$`, and `\/` (or `\?`) for the delimiter
+fn parse_address(address: &str) -> Option<Address> {
+    if let Ok(line) = address.parse() {
+        return Some(Address::Line(line));
+    }
+    let delimiter = address.chars().next().filter(|c| *c == '/' || *c == '?')?;
+    let pattern = address.strip_prefix(delimiter)?.strip_suffix(delimiter)?;
+    let (start, pattern) = match pattern.strip_prefix('^') {
+        Some(pattern) => ("^", pattern),
+        None => ("", pattern),
+    };
+    let (pattern, end) = match pattern.strip_suffix('$') {
+        Some(pattern) if !pattern.ends_with('\\') => (pattern, "$"),
+        _ => (pattern, ""),
+    };
+    let mut literal = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => literal.extend(chars.next()),
+            c => literal.push(c),
+        }
+    }
+    let regex = Regex::new(&format!("{}{}{}", start, regex::escape(&literal), end)).ok()?;
+    Some(Address::Pattern(regex))
+}
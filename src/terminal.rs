@@ -1,10 +1,26 @@
+use crate::config::{CursorShape, CursorStyle};
 pub use crate::editor::Pos;
-use std::io::{self, stdout, Write};
+use std::collections::VecDeque;
+use std::io::{self, stdin, stdout, ErrorKind, Read, Write};
+use std::iter;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 use termion::color;
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::{self, Event, Key};
 use termion::raw::{IntoRawMode, RawTerminal};
 
+// bracketed paste, the terminal sends pasted text between these markers
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+// what is read from the terminal, pasted text is not taken as typed keys
+#[derive(PartialEq)]
+pub enum Input {
+    Key(Key),
+    Paste(String), // with the terminal's "\r" line breaks turned into "\n"
+}
+
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -12,25 +28,83 @@ pub struct Size {
 
 pub struct Terminal {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    rows: u16, // of the whole terminal when its size was last read
+    stdout: Option<RawTerminal<std::io::Stdout>>, // None when headless (tests)
+    // what is read from stdin, by a thread so that reading a key can time out
+    stdin: Receiver<Vec<u8>>,
+    // bytes already read but not yet parsed (e.g. when keys arrive in a
+    // burst)
+    input: VecDeque<u8>,
 }
 
 impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
         let size = termion::terminal_size()?;
-        Ok(Self {
+        let terminal = Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2), // line num of status bar
             },
-            _stdout: stdout().into_raw_mode()?,
-        })
+            rows: size.1,
+            stdout: Some(stdout().into_raw_mode()?),
+            stdin: spawn_stdin_reader(),
+            input: VecDeque::new(),
+        };
+        print!("\x1b[?2004h"); // enable bracketed paste
+        Ok(terminal)
+    }
+
+    // an 80x24 terminal left as it is, reading `keys` as if each was typed
+    // on its own and then as if stdin was closed
+    #[cfg(test)]
+    pub fn headless(keys: &[&str]) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        for key in keys {
+            let _ = sender.send(key.as_bytes().to_vec());
+        }
+        Self {
+            size: Size { width: 80, height: 22 },
+            rows: 24,
+            stdout: None,
+            stdin: receiver,
+            input: VecDeque::new(),
+        }
     }
 
     pub fn size(&self) -> &Size {
         &self.size
     }
 
+    // re-query the terminal size, returns whether it changed (e.g. resized
+    // or the message bar grew), `bar_rows` are the rows of the status and
+    // message bars below the text
+    pub fn update_size(&mut self, bar_rows: u16) -> Result<bool, std::io::Error> {
+        let size = self.terminal_size()?;
+        let height = size.1.saturating_sub(bar_rows);
+        self.rows = size.1;
+        if size.0 == self.size.width && height == self.size.height {
+            return Ok(false);
+        }
+        self.size = Size {
+            width: size.0,
+            height,
+        };
+        Ok(true)
+    }
+
+    // whether the terminal was resized since its size was last read
+    pub fn is_resized(&self) -> bool {
+        self.terminal_size().map_or(false, |size| size != (self.size.width, self.rows))
+    }
+
+    // columns and rows, a headless terminal keeps its size
+    fn terminal_size(&self) -> Result<(u16, u16), std::io::Error> {
+        if self.stdout.is_none() {
+            return Ok((self.size.width, self.rows));
+        }
+        termion::terminal_size()
+    }
+
     pub fn clear_screen() {
         print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
     }
@@ -53,6 +127,17 @@ impl Terminal {
         print!("{}", termion::cursor::Hide);
     }
 
+    pub fn cursor_style(style: CursorStyle) {
+        match (style.shape, style.blink) {
+            (CursorShape::Block, true) => print!("{}", termion::cursor::BlinkingBlock),
+            (CursorShape::Block, false) => print!("{}", termion::cursor::SteadyBlock),
+            (CursorShape::Underline, true) => print!("{}", termion::cursor::BlinkingUnderline),
+            (CursorShape::Underline, false) => print!("{}", termion::cursor::SteadyUnderline),
+            (CursorShape::Bar, true) => print!("{}", termion::cursor::BlinkingBar),
+            (CursorShape::Bar, false) => print!("{}", termion::cursor::SteadyBar),
+        }
+    }
+
     pub fn cursor_show() {
         print!("{}", termion::cursor::Show);
     }
@@ -61,12 +146,74 @@ impl Terminal {
         io::stdout().flush()
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
+    // a key, or the text of a paste, None if nothing comes within `timeout`
+    pub fn read_input_timeout(&mut self, timeout: Duration) -> Result<Option<Input>, std::io::Error> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            if self.input.is_empty() && !self.fill_input(deadline)? {
+                return Ok(None);
+            }
+            if self.input.iter().take(PASTE_START.len()).eq(PASTE_START) {
+                self.input.drain(..PASTE_START.len());
+                return Ok(Some(Input::Paste(self.read_paste()?)));
+            }
+            let first = match self.input.pop_front() {
+                Some(first) => first,
+                None => continue,
+            };
+            // an escape sequence arrives in one read, a lone escape is the key
+            if first == b'\x1b' && self.input.is_empty() {
+                return Ok(Some(Input::Key(Key::Esc)));
+            }
+            let Self { input, stdin, .. } = self;
+            let mut bytes = iter::from_fn(|| {
+                if input.is_empty() {
+                    input.extend(stdin.recv().ok()?);
+                }
+                input.pop_front().map(Ok)
+            });
+            if let Event::Key(key) = event::parse_event(first, &mut bytes)? {
+                return Ok(Some(Input::Key(key)));
+            }
+        }
+    }
+
+    // wait for more input until `deadline` (or for good), returns whether
+    // some came
+    fn fill_input(&mut self, deadline: Option<Instant>) -> Result<bool, std::io::Error> {
+        let bytes = match deadline {
+            Some(deadline) => match self.stdin.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(bytes) => Some(bytes),
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+            None => self.stdin.recv().ok(),
+        };
+        match bytes {
+            Some(bytes) => {
+                self.input.extend(bytes);
+                Ok(true)
+            }
+            None => Err(io::Error::new(ErrorKind::UnexpectedEof, "stdin was closed")),
+        }
+    }
+
+    // the text up to the end marker, with the terminal's "\r" line breaks
+    // turned into "\n"
+    fn read_paste(&mut self) -> Result<String, std::io::Error> {
+        let mut bytes = Vec::new();
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            if self.input.is_empty() {
+                self.fill_input(None)?;
+            }
+            bytes.extend(self.input.drain(..));
+            if let Some(end) = bytes.windows(PASTE_END.len()).position(|window| window == PASTE_END) {
+                self.input.extend(bytes.drain(end..).skip(PASTE_END.len()));
+                break;
             }
         }
+        let text = String::from_utf8_lossy(&bytes);
+        Ok(text.replace("\r\n", "\n").replace('\r', "\n"))
     }
 
     pub fn set_bg_color(color: color::Rgb) {
@@ -85,3 +232,30 @@ impl Terminal {
         print!("{}", color::Fg(color::Reset));
     }
 }
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?2004l");
+        let _ = Self::flush();
+    }
+}
+
+// read stdin on a thread, the bytes come in the chunks they were read in
+fn spawn_stdin_reader() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = stdin();
+        let mut buf = [0; 1024];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    if sender.send(buf.iter().take(read).copied().collect()).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,308 @@
+use crate::Pos;
+
+// where a window is in the document, the current window's is kept by the
+// editor itself
+#[derive(Clone, Copy, Default)]
+pub struct View {
+    pub cursor: Pos,
+    pub offset: Pos,
+    pub scrollbind: bool,
+}
+
+// a part of the screen, in terminal cells
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// how the screen is divided between the windows, the windows are in screen
+// order (left to right, top to bottom) as the views are
+enum Layout {
+    Window,
+    // side by side when `vertical`, stacked otherwise
+    Split { vertical: bool, children: Vec<Layout> },
+}
+
+impl Layout {
+    fn windows(&self) -> usize {
+        match self {
+            Layout::Window => 1,
+            Layout::Split { children, .. } => children.iter().map(Layout::windows).sum(),
+        }
+    }
+
+    // the child holding window `index`, and the index of the window in it
+    fn child_mut(children: &mut [Layout], mut index: usize) -> Option<(usize, &mut Layout, usize)> {
+        for (position, child) in children.iter_mut().enumerate() {
+            let windows = child.windows();
+            if index < windows {
+                return Some((position, child, index));
+            }
+            index = index.saturating_sub(windows);
+        }
+        None
+    }
+
+    // split window `index`, the new window goes before (left of or above) it
+    fn split(&mut self, index: usize, vertical: bool) {
+        match self {
+            Layout::Window => {
+                *self = Layout::Split {
+                    vertical,
+                    children: vec![Layout::Window, Layout::Window],
+                };
+            }
+            Layout::Split { vertical: same, children } => {
+                let same = *same == vertical;
+                if let Some((position, child, index)) = Self::child_mut(children, index) {
+                    // a window of a split in the same direction gets a sibling
+                    if same && matches!(child, Layout::Window) {
+                        children.insert(position, Layout::Window);
+                    } else {
+                        child.split(index, vertical);
+                    }
+                }
+            }
+        }
+    }
+
+    // close window `index` of a split, a split left with a single child is
+    // replaced by it
+    fn close(&mut self, index: usize) {
+        if let Layout::Split { children, .. } = self {
+            if let Some((position, child, index)) = Self::child_mut(children, index) {
+                if matches!(child, Layout::Window) {
+                    children.remove(position);
+                } else {
+                    child.close(index);
+                }
+            }
+            if children.len() == 1 {
+                if let Some(child) = children.pop() {
+                    *self = child;
+                }
+            }
+        }
+    }
+
+    // the area of each window in `area`, divided evenly (the last ones get
+    // what is left over). Side by side windows are separated by a column
+    fn rects(&self, area: Rect, rects: &mut Vec<Rect>) {
+        let (vertical, children) = match self {
+            Layout::Window => {
+                rects.push(area);
+                return;
+            }
+            Layout::Split { vertical, children } => (*vertical, children),
+        };
+        let count = children.len();
+        let total = if vertical {
+            area.width.saturating_sub(count.saturating_sub(1))
+        } else {
+            area.height
+        };
+        let size = total.checked_div(count).unwrap_or(0);
+        let extra = total.checked_rem(count).unwrap_or(0);
+        let mut start = if vertical { area.x } else { area.y };
+        for (index, child) in children.iter().enumerate() {
+            let size = if index >= count.saturating_sub(extra) {
+                size.saturating_add(1)
+            } else {
+                size
+            };
+            let rect = if vertical {
+                Rect { x: start, width: size, ..area }
+            } else {
+                Rect { y: start, height: size, ..area }
+            };
+            child.rects(rect, rects);
+            start = start.saturating_add(size);
+            if vertical {
+                start = start.saturating_add(1); // the separator
+            }
+        }
+    }
+}
+
+pub struct Windows {
+    layout: Layout,
+    views: Vec<View>, // in screen order, the current window's is stale
+    current: usize,
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self {
+            layout: Layout::Window,
+            views: vec![View::default()],
+            current: 0,
+        }
+    }
+}
+
+impl Windows {
+    pub fn len(&self) -> usize {
+        self.views.len()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn view(&self, index: usize) -> Option<View> {
+        self.views.get(index).copied()
+    }
+
+    pub fn set_view(&mut self, index: usize, view: View) {
+        if let Some(old) = self.views.get_mut(index) {
+            *old = view;
+        }
+    }
+
+    pub fn views_mut(&mut self) -> impl Iterator<Item = &mut View> {
+        self.views.iter_mut()
+    }
+
+    // split the current window, showing `view` (the current window's) in
+    // both. The new window is left of it (`vertical`) or above it, and
+    // becomes the current one
+    pub fn split(&mut self, view: View, vertical: bool) {
+        if let Some(current) = self.views.get_mut(self.current) {
+            *current = view;
+        }
+        self.layout.split(self.current, vertical);
+        self.views.insert(self.current, view);
+    }
+
+    // close the current window, the next one (or the previous one, for the
+    // last) becomes current. The last window is not closed, returns whether
+    // it was
+    pub fn close(&mut self) -> bool {
+        if self.views.len() <= 1 {
+            return false;
+        }
+        self.layout.close(self.current);
+        self.views.remove(self.current);
+        if self.current >= self.views.len() {
+            self.current = self.views.len().saturating_sub(1);
+        }
+        true
+    }
+
+    // make window `index` the current one, `view` being the view of the
+    // current window, returns the view of the new current window
+    pub fn switch(&mut self, index: usize, view: View) -> Option<View> {
+        let new_view = self.view(index)?;
+        if let Some(current) = self.views.get_mut(self.current) {
+            *current = view;
+        }
+        self.current = index;
+        Some(new_view)
+    }
+
+    // the screen area of each window in `area`
+    pub fn rects(&self, area: Rect) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        self.layout.rects(area, &mut rects);
+        rects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(y: usize) -> View {
+        View {
+            cursor: Pos { x: 0, y },
+            ..View::default()
+        }
+    }
+
+    fn cursors(windows: &Windows) -> Vec<usize> {
+        (0..windows.len())
+            .filter_map(|index| windows.view(index))
+            .map(|view| view.cursor.y)
+            .collect()
+    }
+
+    // (y, height) of each rect
+    fn rows(rects: &[Rect]) -> Vec<(usize, usize)> {
+        rects.iter().map(|rect| (rect.y, rect.height)).collect()
+    }
+
+    // (x, y, width, height) of each rect
+    fn cells(rects: &[Rect]) -> Vec<(usize, usize, usize, usize)> {
+        rects.iter().map(|rect| (rect.x, rect.y, rect.width, rect.height)).collect()
+    }
+
+    // a window above two side by side ones
+    fn nested() -> Windows {
+        let mut windows = Windows::default();
+        windows.split(View::default(), false);
+        windows.switch(1, View::default());
+        windows.split(View::default(), true);
+        windows
+    }
+
+    const AREA: Rect = Rect { x: 0, y: 0, width: 21, height: 10 };
+
+    #[test]
+    fn split_and_close() {
+        let mut windows = Windows::default();
+        windows.split(view(1), false);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows.current(), 0);
+        assert_eq!(windows.switch(1, view(2)).map(|view| view.cursor.y), Some(1));
+        assert_eq!(cursors(&windows), [2, 1]);
+        assert!(windows.close());
+        assert_eq!(windows.current(), 0);
+        assert_eq!(cursors(&windows), [2]);
+        assert!(!windows.close());
+    }
+
+    #[test]
+    fn rects_share_the_height() {
+        let mut windows = Windows::default();
+        windows.split(View::default(), false);
+        windows.split(View::default(), false);
+        let area = Rect { x: 0, y: 1, width: 80, height: 10 };
+        let rects = windows.rects(area);
+        // the rows left over go to the last windows
+        assert_eq!(rows(&rects), [(1, 3), (4, 3), (7, 4)]);
+        assert!(rects.iter().all(|rect| rect.width == 80));
+    }
+
+    #[test]
+    fn nested_splits() {
+        let windows = nested();
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows.current(), 1);
+        // the bottom half has a column between its windows
+        assert_eq!(cells(&windows.rects(AREA)), [(0, 0, 21, 5), (0, 5, 10, 5), (11, 5, 10, 5)]);
+    }
+
+    #[test]
+    fn split_in_the_same_direction_adds_a_sibling() {
+        let mut windows = Windows::default();
+        windows.split(View::default(), true);
+        windows.split(View::default(), true);
+        let area = Rect { x: 0, y: 0, width: 10, height: 5 };
+        // 8 columns without the separators, the last windows get the 2 left
+        // over
+        assert_eq!(cells(&windows.rects(area)), [(0, 0, 2, 5), (3, 0, 3, 5), (7, 0, 3, 5)]);
+    }
+
+    #[test]
+    fn close_collapses_a_split_of_one_window() {
+        let mut windows = nested();
+        assert!(windows.close());
+        assert_eq!(cells(&windows.rects(AREA)), [(0, 0, 21, 5), (0, 5, 21, 5)]);
+        assert!(windows.close());
+        assert_eq!(cells(&windows.rects(AREA)), [(0, 0, 21, 10)]);
+        assert!(!windows.close());
+    }
+}